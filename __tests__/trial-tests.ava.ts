@@ -1,7 +1,8 @@
 import anyTest, { TestFn } from "ava";
 import { claimTrialAccountDrop, createDrop, createTrialAccountDrop, getDrops, getUserBalance, parseNearAmount, trialCallMethod } from "keypom-js";
-import { NearAccount, Worker } from "near-workspaces";
-import { CONTRACT_METADATA, initKeypomConnection } from "./utils/general";
+import { utils } from "near-api-js";
+import { BN, KeyPair, NEAR, NearAccount, Worker } from "near-workspaces";
+import { CONTRACT_METADATA, DEFAULT_FLOOR_EXIT, deployTrial, initKeypomConnection, LARGE_GAS, trialEvents, trialExecute, trialRules } from "./utils/general";
 const { readFileSync } = require('fs');
 
 const test = anyTest as TestFn<{
@@ -110,4 +111,694 @@ test('Claim trial account drop', async t => {
     //     accountId: funder.accountId,
     // })
     // console.log('drops: ', drops)
-});
\ No newline at end of file
+});
+
+test('Exit status explains why the trial cannot be exited yet', async t => {
+    const {root, funder} = t.context.accounts;
+    // an exit floor of 0 can never be reached without spending the whole balance
+    const trial = await deployTrial(root, funder, { floor: '0' });
+
+    const status: any = await trial.view('get_exit_status', {});
+    t.false(status.exit);
+    t.deepEqual(status.failing, ['floor']);
+    t.is(status.floor_exit, '0');
+    t.is(status.remaining_spend, status.current_floor);
+    t.is(status.repay_schedule, 'fixed');
+    t.is(status.expiry, null);
+    t.is(status.exit_check, null);
+
+    // the user is refused with the same reason
+    await t.throwsAsync(trial.call(trial, 'create_account_and_claim', { new_public_key: (await trial.getKey())!.getPublicKey().toString() }));
+});
+
+test('Key information reports balance, spendable amount and gas', async t => {
+    const {root, funder} = t.context.accounts;
+    const trial = await deployTrial(root, funder, { floor: '0' });
+
+    const info: any = await trial.view('get_key_information', {});
+    // never below the gas keypom clients attached before, and enough for the self callback
+    t.true(new BN(info.required_gas).gte(new BN('50000000000000')));
+    t.true(new BN(info.required_gas).gt(new BN(info.trial_data.callback_gas)));
+    // keypom per use deposit, the spendable amount lives in trial_data
+    t.is(info.yoctoNEAR, '0');
+    t.not(info.balance, '0');
+    t.is(info.trial_data.spendable, info.balance);
+    t.is(info.trial_data.floor_exit, '0');
+    t.deepEqual(info.trial_data.allowances, [{
+        contract_id: `keypom.${root.accountId}`,
+        methods: ['add_to_balance'],
+        amount: NEAR.parse('1 N').toString(),
+    }]);
+});
+
+test('Usage counters track executes, spend and successful calls', async t => {
+    const {root, funder, keypom} = t.context.accounts;
+    const trial = await deployTrial(root, funder);
+
+    const result = await trialExecute(trial, [{
+        receiverId: keypom.accountId,
+        methodName: 'add_to_balance',
+        deposit: NEAR.parse('0.1 N').toString(),
+        gas: '10000000000000',
+    }]);
+    t.true(result.succeeded);
+
+    const usage: any = await trial.view('get_usage', {});
+    t.is(usage.executes, 1);
+    t.is(usage.deposit, NEAR.parse('0.1 N').toString());
+    t.not(usage.gas, '0');
+    t.is(usage.successful_calls, 1);
+    t.is(usage.active_days, 1);
+    t.is(usage.first_activity, usage.last_activity);
+    t.deepEqual(usage.calls, [{ contract_id: keypom.accountId, method_name: 'add_to_balance', calls: 1, successes: 1 }]);
+
+    // separators in the method name would corrupt the stored counters
+    const rejected = await trialExecute(trial, [{
+        receiverId: keypom.accountId,
+        methodName: 'add_to_balance:x;y',
+        deposit: '0',
+        gas: '10000000000000',
+    }]);
+    t.false(rejected.succeeded);
+    t.is((await trial.view('get_usage', {}) as any).executes, 1);
+});
+
+test('Funder can pause and resume the trial', async t => {
+    const {root, funder, keypom} = t.context.accounts;
+    const trial = await deployTrial(root, funder);
+    const addToBalance = { receiverId: keypom.accountId, methodName: 'add_to_balance', gas: '10000000000000' };
+
+    // only the funder may pause
+    await t.throwsAsync(keypom.call(trial, 'pause', { reason: 'review' }));
+
+    const paused = await funder.callRaw(trial, 'pause', { reason: 'review' });
+    t.is(trialEvents(paused)[0].event, 'pause');
+    t.deepEqual(await trial.view('get_pause_status', {}), { paused: true, reason: 'review' });
+    t.false((await trialExecute(trial, [addToBalance])).succeeded);
+
+    // the reason is escaped in the event and the status view
+    const quoted = await funder.callRaw(trial, 'pause', { reason: 'say "hi"' });
+    t.is(trialEvents(quoted)[0].data.reason, 'say "hi"');
+    t.deepEqual(await trial.view('get_pause_status', {}), { paused: true, reason: 'say "hi"' });
+
+    await funder.call(trial, 'resume', {});
+    t.deepEqual(await trial.view('get_pause_status', {}), { paused: false, reason: '' });
+    t.true((await trialExecute(trial, [addToBalance])).succeeded);
+});
+
+test('Funder amends single rules and contract allowances', async t => {
+    const {root, funder, keypom, mapping} = t.context.accounts;
+    const trial = await deployTrial(root, funder);
+
+    await t.throwsAsync(keypom.call(trial, 'update_rules', { repay: '1000' }));
+
+    const updated = await funder.callRaw(trial, 'update_rules', { repay: '1000' });
+    t.deepEqual(trialEvents(updated)[0].data, { changes: [{ rule: 'repay', old: '0', new: '1000' }] });
+    const rules: any = await trial.view('get_rules', {});
+    t.is(rules['|kP|repay'], '1000|kS|');
+    t.is(rules['|kP|floor'], `${DEFAULT_FLOOR_EXIT}|kS|`);
+
+    // every field is validated on its own
+    await t.throwsAsync(funder.call(trial, 'update_rules', { repay: '1N' }));
+
+    // values must not break the comma separated rule lists
+    await t.throwsAsync(funder.call(trial, 'update_rules', { set_contract: `${mapping.accountId},evil.near`, methods: 'set', amount: '0' }));
+    await t.throwsAsync(funder.call(trial, 'update_rules', { set_contract: mapping.accountId, methods: 'set,get', amount: '0' }));
+    await t.throwsAsync(funder.call(trial, 'update_rules', { set_contract: 'Not Valid', methods: 'set', amount: '0' }));
+
+    await funder.call(trial, 'update_rules', { set_contract: mapping.accountId, methods: 'set', amount: '0' });
+    let info: any = await trial.view('get_key_information', {});
+    t.deepEqual(info.trial_data.allowances.map((a: any) => a.contract_id), [keypom.accountId, mapping.accountId]);
+
+    await funder.call(trial, 'update_rules', { remove_contract: keypom.accountId });
+    info = await trial.view('get_key_information', {});
+    t.deepEqual(info.trial_data.allowances.map((a: any) => a.contract_id), [mapping.accountId]);
+    // the last contract cannot be removed
+    await t.throwsAsync(funder.call(trial, 'update_rules', { remove_contract: mapping.accountId }));
+});
+
+test('Locked rules cannot turn against the user once activated', async t => {
+    const {root, funder, keypom} = t.context.accounts;
+    const locked = { locked: 'true' };
+    const trial = await deployTrial(root, funder, locked);
+
+    // not activated yet, the funder may still replace the rules
+    await funder.call(trial, 'setup', trialRules(root, funder, locked));
+    t.false(((await trial.view('get_rules', {})) as any).activated);
+
+    await trialExecute(trial, [{ receiverId: keypom.accountId, methodName: 'add_to_balance', gas: '10000000000000' }]);
+    t.true(((await trial.view('get_rules', {})) as any).activated);
+
+    await t.throwsAsync(funder.call(trial, 'setup', trialRules(root, funder, { ...locked, repay: NEAR.parse('1 N').toString() })));
+    await t.throwsAsync(funder.call(trial, 'setup', trialRules(root, funder, { ...locked, floor: '0' })));
+    await t.throwsAsync(funder.call(trial, 'setup', trialRules(root, funder, { ...locked, funder: keypom.accountId })));
+    await t.throwsAsync(funder.call(trial, 'update_rules', { repay: '1' }));
+    // changes in the user's favor are still allowed
+    await funder.call(trial, 'setup', trialRules(root, funder, { ...locked, floor: NEAR.parse('200 N').toString() }));
+
+    // a pause would block the user indefinitely, it is refused once the rules are locked
+    await t.throwsAsync(funder.call(trial, 'pause', { reason: 'hold' }));
+    t.deepEqual(await trial.view('get_pause_status', {}), { paused: false, reason: '' });
+});
+
+test('Funder takeover follows the takeover policy', async t => {
+    const {root, funder} = t.context.accounts;
+    // the user could not exit, the funder skips the exit conditions
+    const rules = { amounts: '0', floor: '0' };
+    const trial = await deployTrial(root, funder, { ...rules, takeover: 'never' });
+    const funderKey = KeyPair.fromRandom('ed25519').getPublicKey().toString();
+
+    t.is(((await trial.view('get_rules', {})) as any).takeover_policy, 'never');
+    await t.throwsAsync(funder.call(trial, 'create_account_and_claim', { new_public_key: funderKey }));
+
+    // a day of inactivity has not passed since setup
+    await funder.call(trial, 'setup', trialRules(root, funder, { ...rules, takeover: 'inactivity', takeover_inactivity: '86400000000000' }));
+    await t.throwsAsync(funder.call(trial, 'create_account_and_claim', { new_public_key: funderKey }));
+
+    // the trial has not expired yet
+    const expiry = new BN(Date.now()).mul(new BN('1000000')).add(new BN('86400000000000')).toString();
+    await funder.call(trial, 'setup', trialRules(root, funder, { ...rules, takeover: 'expiry', expiry }));
+    await t.throwsAsync(funder.call(trial, 'create_account_and_claim', { new_public_key: funderKey }));
+
+    await funder.call(trial, 'setup', trialRules(root, funder, rules));
+    t.is(((await trial.view('get_rules', {})) as any).takeover_policy, 'always');
+    const trialKey = (await trial.getKey())!.getPublicKey().toString();
+    await funder.call(trial, 'create_account_and_claim', { new_public_key: funderKey });
+    // the funder signs with its own key, the trial key is left for the funder to remove
+    const keys = await trial.viewAccessKeys();
+    t.deepEqual(keys.keys.map((k: any) => k.public_key).sort(), [funderKey, trialKey].sort());
+});
+
+test('Repay declines along the configured steps', async t => {
+    const {root, funder, keypom} = t.context.accounts;
+    const trial = await deployTrial(root, funder, {
+        repay: NEAR.parse('1 N').toString(),
+        repay_schedule: 'steps',
+        repay_steps: `1:${NEAR.parse('0.5 N').toString()},2:0`,
+    });
+
+    let status: any = await trial.view('get_exit_status', {});
+    t.is(status.repay_schedule, 'steps');
+    t.is(status.repay, NEAR.parse('1 N').toString());
+
+    const addToBalance = { receiverId: keypom.accountId, methodName: 'add_to_balance', gas: '10000000000000' };
+    await trialExecute(trial, [addToBalance]);
+    status = await trial.view('get_exit_status', {});
+    t.is(status.repay, NEAR.parse('0.5 N').toString());
+
+    await trialExecute(trial, [addToBalance]);
+    status = await trial.view('get_exit_status', {});
+    t.is(status.repay, '0');
+});
+
+test('Percentage repay and repay split on exit', async t => {
+    const {root, funder} = t.context.accounts;
+    const beneficiary = await root.createSubAccount('beneficiary');
+    const trial = await deployTrial(root, funder, {
+        repay: NEAR.parse('1 N').toString(),
+        repay_percent: '5000',
+    });
+
+    // the larger of the fixed repay and half the balance
+    const status: any = await trial.view('get_exit_status', {});
+    const halfBalance = new BN(status.balance).div(new BN(10000)).mul(new BN(5000));
+    t.is(status.repay, halfBalance.toString());
+
+    // splits that could never be paid out are refused up front
+    const fixedRepay = { amounts: '0', repay: NEAR.parse('1 N').toString() };
+    await t.throwsAsync(funder.call(trial, 'setup', trialRules(root, funder, { ...fixedRepay, repay_split: `${beneficiary.accountId}:20000` })));
+    await t.throwsAsync(funder.call(trial, 'setup', trialRules(root, funder, { ...fixedRepay, repay_split: 'Not Valid:2500' })));
+
+    // a quarter of the fixed repay goes to the beneficiary, the rest to the funder
+    await funder.call(trial, 'setup', trialRules(root, funder, { ...fixedRepay, repay_split: `${beneficiary.accountId}:2500` }));
+    const beneficiaryBefore = (await beneficiary.balance()).total;
+    const funderBefore = (await funder.balance()).total;
+    await trial.call(trial, 'create_account_and_claim', { new_public_key: KeyPair.fromRandom('ed25519').getPublicKey().toString() });
+    t.is((await beneficiary.balance()).total.sub(beneficiaryBefore).toString(), NEAR.parse('0.25 N').toString());
+    t.is((await funder.balance()).total.sub(funderBefore).toString(), NEAR.parse('0.75 N').toString());
+});
+
+test('External exit check must return true before the user exits', async t => {
+    const {root, funder, mapping} = t.context.accounts;
+    const trial = await deployTrial(root, funder, {
+        exit_check_contract: mapping.accountId,
+        exit_check_method: 'get_account_id',
+    });
+
+    const status: any = await trial.view('get_exit_status', {});
+    t.deepEqual(status.exit_check, { contract_id: mapping.accountId, method_name: 'get_account_id' });
+
+    // the view fails without a public key, so the exit is not carried out
+    const trialKey = (await trial.getKey())!.getPublicKey().toString();
+    const result = await trial.callRaw(trial, 'create_account_and_claim', {
+        new_public_key: KeyPair.fromRandom('ed25519').getPublicKey().toString(),
+    }, { gas: LARGE_GAS });
+    t.true(result.logs.includes('exit check failed'));
+    const keys = await trial.viewAccessKeys();
+    t.deepEqual(keys.keys.map((k: any) => k.public_key), [trialKey]);
+    t.truthy(await trial.view('get_rules', {}));
+});
+
+test('External exit check returning true lets the user exit', async t => {
+    const {root, funder} = t.context.accounts;
+    const stub = await root.createSubAccount('stub');
+    await stub.deploy('./out/stub.wasm');
+    const trial = await deployTrial(root, funder, {
+        exit_check_contract: stub.accountId,
+        exit_check_method: 'is_eligible',
+    });
+    const newKey = KeyPair.fromRandom('ed25519').getPublicKey().toString();
+
+    // false keeps the trial as it is
+    let result = await trial.callRaw(trial, 'create_account_and_claim', { new_public_key: newKey }, { gas: LARGE_GAS });
+    t.true(result.logs.includes('exit check failed'));
+
+    await stub.call(stub, 'set_eligible', { eligible: true });
+    result = await trial.callRaw(trial, 'create_account_and_claim', { new_public_key: newKey }, { gas: LARGE_GAS });
+    t.true(result.succeeded);
+    const keys = await trial.viewAccessKeys();
+    t.deepEqual(keys.keys.map((k: any) => k.public_key), [newKey]);
+    t.deepEqual(await trial.viewCode(), Buffer.from([]));
+});
+
+test('Activity exit conditions count only real successful calls', async t => {
+    const {root, funder, keypom} = t.context.accounts;
+    const trial = await deployTrial(root, funder, {
+        exit_calls: '1',
+        exit_methods: `${keypom.accountId}:add_to_balance`,
+    });
+
+    let status: any = await trial.view('get_exit_status', {});
+    t.deepEqual(status.failing, ['calls', 'methods']);
+
+    // nobody but the trial itself can report successful calls
+    await t.throwsAsync(funder.call(trial, 'callback', Buffer.from(`0,0,${keypom.accountId},add_to_balance`)));
+    status = await trial.view('get_exit_status', {});
+    t.is(status.successful_calls, 0);
+
+    await trialExecute(trial, [{ receiverId: keypom.accountId, methodName: 'add_to_balance', gas: '10000000000000' }]);
+    status = await trial.view('get_exit_status', {});
+    t.is(status.successful_calls, 1);
+    t.true(status.exit);
+    t.deepEqual(status.failing, []);
+});
+
+test('Exit to an existing account sends the rest after repay', async t => {
+    const {root, funder} = t.context.accounts;
+    const beneficiary = await root.createSubAccount('beneficiary');
+    const trial = await deployTrial(root, funder, { repay: NEAR.parse('1 N').toString() });
+
+    // only the trial key may exit to an account
+    await t.throwsAsync(funder.call(trial, 'exit_to_account', { account_id: beneficiary.accountId }));
+
+    const before = (await beneficiary.balance()).total;
+    const result = await trial.callRaw(trial, 'exit_to_account', { account_id: beneficiary.accountId }, { gas: LARGE_GAS });
+    const event = trialEvents(result).find((e) => e.event === 'exit_to_account')!;
+    t.is(event.data.repay, NEAR.parse('1 N').toString());
+    t.false(event.data.deleted);
+    // the repay is not taken twice, only the storage reserve stays behind
+    t.is((await beneficiary.balance()).total.sub(before).toString(), event.data.amount);
+    t.true(new BN(event.data.amount).gt(NEAR.parse('3 N')));
+    // the trial state, code and key are gone, the same exit cannot run again
+    t.deepEqual(await trial.viewCode(), Buffer.from([]));
+    t.deepEqual((await trial.viewAccessKeys()).keys, []);
+    await t.throwsAsync(trial.call(trial, 'exit_to_account', { account_id: beneficiary.accountId }));
+});
+
+test('Claiming ignores an account_id meant for exit_to_account', async t => {
+    const {root, funder} = t.context.accounts;
+    const beneficiary = await root.createSubAccount('beneficiary');
+    const trial = await deployTrial(root, funder);
+
+    const before = (await beneficiary.balance()).total;
+    const newKey = KeyPair.fromRandom('ed25519').getPublicKey().toString();
+    await trial.call(trial, 'create_account_and_claim', { new_public_key: newKey, account_id: beneficiary.accountId });
+    const keys = await trial.viewAccessKeys();
+    t.deepEqual(keys.keys.map((k: any) => k.public_key), [newKey]);
+    t.is((await beneficiary.balance()).total.toString(), before.toString());
+});
+
+test('Exit to an existing account can delete the trial', async t => {
+    const {root, funder} = t.context.accounts;
+    const beneficiary = await root.createSubAccount('beneficiary');
+    const trial = await deployTrial(root, funder);
+
+    const before = (await beneficiary.balance()).total;
+    await trial.call(trial, 'exit_to_account', { account_id: beneficiary.accountId, delete_account: true }, { gas: LARGE_GAS });
+    t.false(await trial.exists());
+    t.true((await beneficiary.balance()).total.sub(before).gt(NEAR.parse('4 N')));
+});
+
+test('User exits with a contract staged by the funder', async t => {
+    const {root, funder} = t.context.accounts;
+    const trial = await deployTrial(root, funder);
+    const code = readFileSync('./out/mapping.wasm');
+    // the funder pays for the staged code kept in trial storage
+    const attachedDeposit = new BN(code.length).mul(new BN('10000000000000000000')).toString();
+
+    await t.throwsAsync(trial.call(trial, 'stage_contract', code, { gas: LARGE_GAS, attachedDeposit }));
+    await t.throwsAsync(funder.call(trial, 'stage_contract', code, { gas: LARGE_GAS }));
+    await funder.call(trial, 'stage_contract', code, { gas: LARGE_GAS, attachedDeposit });
+    let hashes: string[] = await trial.view('get_staged_contract_hashes', {});
+    t.is(hashes.length, 1);
+
+    // unstaging removes the code and returns the deposit
+    await t.throwsAsync(trial.call(trial, 'unstage_contract', { hash: hashes[0] }));
+    const unstaged = await funder.callRaw(trial, 'unstage_contract', { hash: hashes[0] });
+    t.is(trialEvents(unstaged)[0].data.size, code.length);
+    t.deepEqual(await trial.view('get_staged_contract_hashes', {}), []);
+
+    await funder.call(trial, 'stage_contract', code, { gas: LARGE_GAS, attachedDeposit });
+    hashes = await trial.view('get_staged_contract_hashes', {});
+
+    const newKey = KeyPair.fromRandom('ed25519').getPublicKey().toString();
+    // only staged code can be deployed
+    await t.throwsAsync(trial.call(trial, 'create_account_and_claim', { new_public_key: newKey, contract_hash: '11111111111111111111111111111111' }));
+
+    await trial.call(trial, 'create_account_and_claim', { new_public_key: newKey, contract_hash: hashes[0] }, { gas: LARGE_GAS });
+    t.deepEqual(await trial.viewCode(), code);
+});
+
+test('Claim adds several keys including secp256k1', async t => {
+    const {root, funder} = t.context.accounts;
+    const trial = await deployTrial(root, funder);
+
+    // uncompressed secp256k1 generator point, a valid public key without the 0x04 prefix
+    const secpKey = 'secp256k1:' + utils.serialize.base_encode(Buffer.from(
+        '79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798' +
+        '483ada7726a3c4655da4fbfc0e1108a8fd17b448a68554199c47d08ffb10d4b8',
+        'hex'
+    ));
+    const edKey = KeyPair.fromRandom('ed25519').getPublicKey().toString();
+
+    await t.throwsAsync(trial.call(trial, 'create_account_and_claim', { new_public_keys: [edKey, 'rsa:abc'] }));
+    await t.throwsAsync(trial.call(trial, 'create_account_and_claim', { new_public_keys: [edKey.slice(0, -4)] }));
+
+    await t.throwsAsync(trial.call(trial, 'create_account_and_claim', { new_public_keys: [] }));
+
+    // any json formatting of the array is accepted
+    await trial.call(trial, 'create_account_and_claim', Buffer.from(`{"new_public_keys": [ "${edKey}",\n "${secpKey}" ]}`));
+    const keys = await trial.viewAccessKeys();
+    t.deepEqual(keys.keys.map((k: any) => k.public_key).sort(), [edKey, secpKey].sort());
+});
+
+test('Trial key stays as a bounded function call key after exit', async t => {
+    const {root, funder, keypom} = t.context.accounts;
+    const trial = await deployTrial(root, funder);
+    const trialKey = (await trial.getKey())!.getPublicKey().toString();
+    const newKey = KeyPair.fromRandom('ed25519').getPublicKey().toString();
+    const claim = {
+        new_public_key: newKey,
+        trial_key_receiver_id: keypom.accountId,
+        trial_key_method_names: 'add_to_balance',
+    };
+
+    // an unlimited allowance is never granted
+    await t.throwsAsync(trial.call(trial, 'create_account_and_claim', claim));
+    await t.throwsAsync(trial.call(trial, 'create_account_and_claim', { ...claim, trial_key_allowance: '0' }));
+
+    const allowance = NEAR.parse('0.25 N').toString();
+    await trial.call(trial, 'create_account_and_claim', { ...claim, trial_key_allowance: allowance });
+    const keys = await trial.viewAccessKeys();
+    const kept: any = keys.keys.find((k: any) => k.public_key === trialKey);
+    t.deepEqual(kept.access_key.permission, {
+        FunctionCall: { allowance, receiver_id: keypom.accountId, method_names: ['add_to_balance'] },
+    });
+    t.true(keys.keys.some((k: any) => k.public_key === newKey && k.access_key.permission === 'FullAccess'));
+});
+
+test('User forfeits the trial back to the funder', async t => {
+    const {root, funder} = t.context.accounts;
+    const trial = await deployTrial(root, funder);
+
+    // only the trial key may forfeit
+    await t.throwsAsync(funder.call(trial, 'forfeit', {}));
+
+    const before = (await funder.balance()).total;
+    const result = await trial.callRaw(trial, 'forfeit', {});
+    const event = trialEvents(result)[0];
+    t.is(event.event, 'forfeit');
+    t.is(event.data.funder, funder.accountId);
+    t.false(await trial.exists());
+    t.true((await funder.balance()).total.sub(before).gt(NEAR.parse('4.5 N')));
+});
+
+test('Referrer is paid once the user exits', async t => {
+    const {root, funder} = t.context.accounts;
+    const referrer = await root.createSubAccount('referrer');
+    const reward = NEAR.parse('0.1 N').toString();
+    const trial = await deployTrial(root, funder, { referrer: referrer.accountId, referral_reward: reward });
+
+    const status: any = await trial.view('get_exit_status', {});
+    t.is(status.referral_reward, reward);
+    await t.throwsAsync(funder.call(trial, 'setup', trialRules(root, funder, { referrer: 'Not Valid', referral_reward: reward })));
+    await t.throwsAsync(funder.call(trial, 'setup', trialRules(root, funder, { referrer: referrer.accountId, referral_reward: 'lots' })));
+
+    const before = (await referrer.balance()).total;
+    const result = await trial.callRaw(trial, 'create_account_and_claim', {
+        new_public_key: KeyPair.fromRandom('ed25519').getPublicKey().toString(),
+    });
+    t.true(result.succeeded);
+    t.deepEqual(trialEvents(result).find((e) => e.event === 'referral_payout')!.data, { referrer: referrer.accountId, amount: reward });
+    t.is((await referrer.balance()).total.sub(before).toString(), reward);
+});
+
+test('Grace period warns and relaxes exit conditions before expiry', async t => {
+    const {root, funder, keypom} = t.context.accounts;
+    const hourNs = new BN(3600).mul(new BN(1e9));
+    const nowNs = new BN(Date.now()).mul(new BN(1e6));
+    const rules = {
+        amounts: '0',
+        floor: '0',
+        expiry: nowNs.add(hourNs).toString(),
+        grace_period: hourNs.muln(2).toString(),
+        grace_relax: 'floor',
+    };
+    const trial = await deployTrial(root, funder, rules);
+
+    const remaining: any = await trial.view('get_time_remaining', {});
+    t.false(remaining.expired);
+    t.true(remaining.in_grace_period);
+    // the unreachable floor is waived during the grace period
+    const status: any = await trial.view('get_exit_status', {});
+    t.true(status.exit);
+
+    const addToBalance = { receiverId: keypom.accountId, methodName: 'add_to_balance', gas: '10000000000000' };
+    const result = await trialExecute(trial, [addToBalance]);
+    t.true(result.succeeded);
+    t.is(trialEvents(result)[0].event, 'expiry_warning');
+
+    // expiry and grace_period are timestamps and must fit a u64
+    await t.throwsAsync(funder.call(trial, 'update_rules', { expiry: '18446744073709551616' }));
+    await t.throwsAsync(funder.call(trial, 'update_rules', { grace_period: '-1' }));
+
+    // once expired the trial can no longer be used
+    await funder.call(trial, 'setup', trialRules(root, funder, { ...rules, expiry: nowNs.sub(hourNs).toString() }));
+    t.true(((await trial.view('get_time_remaining', {})) as any).expired);
+    t.false((await trialExecute(trial, [addToBalance])).succeeded);
+});
+
+test('Argument constraints check top level fields of the call args', async t => {
+    const {root, funder, keypom} = t.context.accounts;
+    const trial = await deployTrial(root, funder, {
+        arg_constraints: `${keypom.accountId}:add_to_balance:memo:in:ok|fine;${keypom.accountId}:add_to_*:amount:max:10`,
+    });
+    const call = (args: Record<string, any> | string) => trialExecute(trial, [{
+        receiverId: keypom.accountId,
+        methodName: 'add_to_balance',
+        args,
+        gas: '10000000000000',
+    }]);
+
+    t.true((await call({ memo: 'ok', amount: '10' })).succeeded);
+    t.true((await call({ memo: 'fine', amount: 3 })).succeeded);
+    // missing fields and values out of bounds
+    t.false((await call({ amount: '1' })).succeeded);
+    t.false((await call({ memo: 'no', amount: '1' })).succeeded);
+    t.false((await call({ memo: 'ok', amount: '11' })).succeeded);
+    // look-alikes in nested objects or strings are not the top level field
+    t.false((await call({ nested: { memo: 'ok' }, amount: '1' })).succeeded);
+    t.false((await call({ note: '"memo":"ok"', amount: '1' })).succeeded);
+    // duplicate fields and malformed json are rejected outright
+    t.false((await call('{"memo":"no","memo":"ok","amount":"1"}')).succeeded);
+    t.false((await call('{"memo":"ok","amount":"1"')).succeeded);
+});
+
+test('Argument constraints substitute the trial and funder accounts', async t => {
+    const {root, funder, keypom} = t.context.accounts;
+    const trial = await deployTrial(root, funder, {
+        arg_constraints: `${keypom.accountId}:add_to_balance:account_id:eq:$current_account_id;${keypom.accountId}:add_to_balance:refund_to:in:$current_account_id|$funder`,
+    });
+    const call = (args: Record<string, any>) => trialExecute(trial, [{
+        receiverId: keypom.accountId,
+        methodName: 'add_to_balance',
+        args,
+        gas: '10000000000000',
+    }]);
+
+    t.true((await call({ account_id: trial.accountId, refund_to: trial.accountId })).succeeded);
+    t.true((await call({ account_id: trial.accountId, refund_to: funder.accountId })).succeeded);
+    t.false((await call({ account_id: funder.accountId, refund_to: funder.accountId })).succeeded);
+    t.false((await call({ account_id: trial.accountId, refund_to: keypom.accountId })).succeeded);
+    // the placeholder itself is not accepted as a value
+    t.false((await call({ account_id: '$current_account_id', refund_to: trial.accountId })).succeeded);
+});
+
+test('Fungible token transfers are capped per call and in total', async t => {
+    const {root, funder, keypom} = t.context.accounts;
+    const trial = await deployTrial(root, funder, {
+        methods: 'ft_transfer',
+        amounts: '1',
+        ft_allowances: `${keypom.accountId}:100:150`,
+    });
+    const transfer = (amount: string) => ({
+        receiverId: keypom.accountId,
+        methodName: 'ft_transfer',
+        args: { receiver_id: funder.accountId, amount },
+        deposit: '1',
+        gas: '10000000000000',
+    });
+
+    t.false((await trialExecute(trial, [transfer('101')])).succeeded);
+    t.false((await trialExecute(trial, [transfer('100'), transfer('100')])).succeeded);
+
+    // keypom is no token, the transfers fail and their allowance is given back
+    const result = await trialExecute(trial, [transfer('100'), transfer('50')]);
+    t.true(result.succeeded);
+    t.true(result.logs.includes('promise failed'));
+    t.deepEqual(await trial.view('get_ft_allowances', {}), [{ token_id: keypom.accountId, per_call: '100', total: '150', spent: '0' }]);
+
+    // the refund cannot be triggered from outside to reset the spent total
+    await t.throwsAsync(funder.call(trial, 'callback', Buffer.from(`0,0,${keypom.accountId},ft_transfer,150`)));
+});
+
+test('NFT transfers and approvals are locked until the user can exit', async t => {
+    const {root, funder, keypom} = t.context.accounts;
+    const nftMethods = ['nft_transfer', 'nft_transfer_call', 'nft_transfer_payout', 'nft_approve', 'nft_batch_transfer', 'nft_batch_approve'];
+    const rules = {
+        methods: [...nftMethods, 'nft_token', 'add_to_balance'].join(':'),
+        amounts: '1',
+        floor: '0',
+        nft_locked: keypom.accountId,
+    };
+    const trial = await deployTrial(root, funder, rules);
+    const call = (methodName: string) => trialExecute(trial, [{
+        receiverId: keypom.accountId,
+        methodName,
+        args: { receiver_id: funder.accountId, token_id: '1' },
+        deposit: '1',
+        gas: '10000000000000',
+    }]);
+
+    for (const methodName of nftMethods) {
+        t.false((await call(methodName)).succeeded, methodName);
+    }
+    // other methods on the locked contract still work
+    t.true((await call('nft_token')).succeeded);
+    t.true((await call('add_to_balance')).succeeded);
+
+    // reachable exit conditions unlock the tokens
+    await funder.call(trial, 'setup', trialRules(root, funder, { ...rules, floor: DEFAULT_FLOOR_EXIT }));
+    t.true((await call('nft_transfer')).succeeded);
+});
+
+test('Method globs allow and deny entries take precedence', async t => {
+    const {root, funder, keypom} = t.context.accounts;
+    const trial = await deployTrial(root, funder, { methods: '*:!withdraw_*:!create_drop' });
+    const call = (methodName: string) => trialExecute(trial, [{
+        receiverId: keypom.accountId,
+        methodName,
+        gas: '10000000000000',
+    }]);
+
+    t.true((await call('add_to_balance')).succeeded);
+    t.false((await call('withdraw_from_balance')).succeeded);
+    t.false((await call('create_drop')).succeeded);
+
+    await funder.call(trial, 'update_rules', { set_contract: keypom.accountId, methods: 'add_*:!add_to_balance', amount: '0' });
+    const info: any = await trial.view('get_key_information', {});
+    t.deepEqual(info.trial_data.allowances[0].methods, ['add_*', '!add_to_balance']);
+    t.false((await call('add_to_balance')).succeeded);
+});
+
+test('Contract entries match sub-accounts by suffix', async t => {
+    const {root, funder, keypom, mapping} = t.context.accounts;
+    const trial = await deployTrial(root, funder, {
+        contracts: `*.${root.accountId},${keypom.accountId}`,
+        methods: '*,add_to_balance:ft_transfer',
+        amounts: '1,1',
+        ft_allowances: `*.${root.accountId}:100:150`,
+    });
+
+    // the parent itself is not a sub-account
+    t.false((await trialExecute(trial, [{ receiverId: root.accountId, methodName: 'new', gas: '10000000000000' }])).succeeded);
+    t.true((await trialExecute(trial, [{ receiverId: mapping.accountId, methodName: 'set', gas: '10000000000000' }])).succeeded);
+    // the exact entry wins over the suffix and only allows its own methods
+    t.false((await trialExecute(trial, [{ receiverId: keypom.accountId, methodName: 'create_drop', gas: '10000000000000' }])).succeeded);
+    t.true((await trialExecute(trial, [{ receiverId: keypom.accountId, methodName: 'add_to_balance', gas: '10000000000000' }])).succeeded);
+    // the suffix allowance caps every token under the parent
+    t.false((await trialExecute(trial, [{
+        receiverId: keypom.accountId,
+        methodName: 'ft_transfer',
+        args: { receiver_id: funder.accountId, amount: '101' },
+        deposit: '1',
+        gas: '10000000000000',
+    }])).succeeded);
+
+    await funder.call(trial, 'update_rules', { set_contract: `*.${mapping.accountId}`, methods: '*', amount: '0' });
+    await t.throwsAsync(funder.call(trial, 'update_rules', { set_contract: '*.', methods: '*', amount: '0' }));
+});
+
+test('Storage deposit registration is prepended for flagged contracts', async t => {
+    const {root, funder, keypom} = t.context.accounts;
+    const rules = {
+        amounts: '0',
+        // the exact entry wins over the suffix entry
+        storage_deposit: `*.${root.accountId}:1,${keypom.accountId}:1250000000000000000000`,
+    };
+    const trial = await deployTrial(root, funder, rules);
+    const addToBalance = { receiverId: keypom.accountId, methodName: 'add_to_balance', gas: '10000000000000' };
+    const flaggedInfo: any = await trial.view('get_key_information', {});
+
+    // keypom has no storage_deposit, so the registration fails the whole batch
+    const flagged = await trialExecute(trial, [addToBalance]);
+    t.true(flagged.logs.includes('promise failed'));
+    t.true(JSON.stringify(flagged.result.receipts_outcome).includes('MethodNotFound'));
+    t.is(((await trial.view('get_usage', {})) as any).successful_calls, 0);
+
+    const { storage_deposit, ...unflagged } = rules;
+    await funder.call(trial, 'setup', trialRules(root, funder, unflagged));
+    // the registration needs gas on top of every transaction
+    const plainInfo: any = await trial.view('get_key_information', {});
+    t.true(new BN(flaggedInfo.required_gas).gt(new BN(plainInfo.required_gas)));
+    const plain = await trialExecute(trial, [addToBalance]);
+    t.false(plain.logs.includes('promise failed'));
+    t.is(((await trial.view('get_usage', {})) as any).successful_calls, 1);
+});
+
+test('Storage deposit registers the trial on a NEP-145 contract', async t => {
+    const {root, funder} = t.context.accounts;
+    const stub = await root.createSubAccount('stub');
+    await stub.deploy('./out/stub.wasm');
+    const trial = await deployTrial(root, funder, {
+        contracts: stub.accountId,
+        methods: 'set_eligible',
+        amounts: '0',
+        storage_deposit: `${stub.accountId}:${NEAR.parse('0.01 N').toString()}`,
+    });
+    const setEligible = { receiverId: stub.accountId, methodName: 'set_eligible', args: { eligible: true }, gas: '10000000000000' };
+    t.is(await stub.view('storage_balance_of', { account_id: trial.accountId }), null);
+
+    const result = await trialExecute(trial, [setEligible]);
+    t.true(result.succeeded);
+    t.false(result.logs.includes('promise failed'));
+    const bounds: any = await stub.view('storage_balance_bounds', {});
+    t.deepEqual(await stub.view('storage_balance_of', { account_id: trial.accountId }), { total: bounds.min, available: '0' });
+    t.true(await stub.view('is_eligible', {}));
+
+    // already registered, the whole deposit comes back and only the calls themselves count against the floor
+    t.true((await trialExecute(trial, [setEligible])).succeeded);
+    const usage: any = await trial.view('get_usage', {});
+    t.is(usage.successful_calls, 2);
+    t.is(usage.deposit, '0');
+});
@@ -228,4 +228,85 @@ export async function queryAllViewFunctions(
     keyTotalSupply: keyTotalSupply,
     keys: getKeys,
   }
-}
\ No newline at end of file
+}
+export const TRIAL_WASM: string = "./out/trial.wasm";
+export const DEFAULT_FLOOR_EXIT: string = NEAR.parse("100 N").toString();
+
+// rules and transaction params are read by the trial contract as "|kP|key":"value|kS|"
+export function wrapParams(
+  params: Record<string, string>
+): Record<string, string> {
+  const wrapped: Record<string, string> = {};
+  Object.entries(params).forEach(([key, value]) => {
+    wrapped[`|kP|${key}`] = `${value}|kS|`;
+  });
+  return wrapped;
+}
+
+// full setup args for a trial, the defaults allow add_to_balance on keypom
+// and exiting without spending anything, rules override single fields
+export function trialRules(
+  root: NearAccount,
+  funder: NearAccount,
+  rules: Record<string, string> = {}
+): Record<string, string> {
+  return wrapParams({
+    funder: funder.accountId,
+    contracts: `keypom.${root.accountId}`,
+    methods: 'add_to_balance',
+    amounts: NEAR.parse("1 N").toString(),
+    repay: '0',
+    floor: DEFAULT_FLOOR_EXIT,
+    ...rules,
+  });
+}
+
+// deploy the trial contract to a fresh sub-account of root and set it up for the funder
+export async function deployTrial(
+  root: NearAccount,
+  funder: NearAccount,
+  rules: Record<string, string> = {},
+  initialBalance: string = NEAR.parse("5 N").toString()
+): Promise<NearAccount> {
+  const trial = await root.createSubAccount('trial', { initialBalance });
+  await trial.deploy(TRIAL_WASM);
+  await trial.call(trial, 'setup', trialRules(root, funder, rules));
+  return trial;
+}
+
+export type TrialTransaction = {
+  receiverId: string,
+  methodName: string,
+  args?: Record<string, any> | string,
+  deposit?: string,
+  gas?: string,
+}
+
+// sign execute with the trial key, one function call per transaction
+export async function trialExecute(
+  trial: NearAccount,
+  transactions: TrialTransaction[]
+): Promise<TransactionResult> {
+  return trial.callRaw(trial, 'execute', {
+    transactions: transactions.map(({ receiverId, methodName, args = {}, deposit = '0', gas = DEFAULT_GAS }) => ({
+      '|kR|': receiverId,
+      '|kA|': 'FunctionCall',
+      ...wrapParams({
+        methodName,
+        args: typeof args === 'string' ? args : JSON.stringify(args),
+        deposit,
+        gas,
+      }),
+    })),
+  }, { gas: LARGE_GAS });
+}
+
+// NEP-297 events emitted by the trial contract during a transaction
+export function trialEvents(
+  transaction: TransactionResult
+): { event: string, data: any }[] {
+  return transaction.logs
+    .filter((log) => log.startsWith('EVENT_JSON:'))
+    .map((log) => JSON.parse(log.slice('EVENT_JSON:'.length)))
+    .filter((event) => event.standard === 'trial_accounts');
+}
@@ -0,0 +1,6 @@
+#!/bin/bash
+set -e
+
+cargo build --target wasm32-unknown-unknown --release
+mkdir -p ../out
+cp target/wasm32-unknown-unknown/release/*.wasm ../out/stub.wasm
@@ -0,0 +1,78 @@
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U128;
+use near_sdk::serde::Serialize;
+use near_sdk::{env, near_bindgen, AccountId, Balance, Promise};
+
+// registration only NEP-145 storage, the minimum covers a single account entry
+const STORAGE_BALANCE_MIN: Balance = 1_250_000_000_000_000_000_000;
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StorageBalance {
+    total: U128,
+    available: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StorageBalanceBounds {
+    min: U128,
+    max: Option<U128>,
+}
+
+// stands in for third party contracts in the trial account tests
+#[near_bindgen]
+#[derive(BorshSerialize, BorshDeserialize, Default)]
+pub struct Stub {
+    eligible: bool,
+    registered: Vec<AccountId>,
+}
+
+#[near_bindgen]
+impl Stub {
+    pub fn set_eligible(&mut self, eligible: bool) {
+        self.eligible = eligible;
+    }
+
+    pub fn is_eligible(&self) -> bool {
+        self.eligible
+    }
+
+    // always registration only, anything above the minimum is refunded
+    #[payable]
+    pub fn storage_deposit(&mut self, account_id: Option<AccountId>) -> StorageBalance {
+        let account_id = account_id.unwrap_or_else(env::predecessor_account_id);
+        let deposit = env::attached_deposit();
+        let refund = if self.registered.contains(&account_id) {
+            deposit
+        } else {
+            assert!(deposit >= STORAGE_BALANCE_MIN, "ERR_NOT_ENOUGH_DEPOSIT");
+            self.registered.push(account_id);
+            deposit - STORAGE_BALANCE_MIN
+        };
+        if refund > 0 {
+            Promise::new(env::predecessor_account_id()).transfer(refund);
+        }
+        Self::registered_balance()
+    }
+
+    pub fn storage_balance_of(&self, account_id: AccountId) -> Option<StorageBalance> {
+        self.registered.contains(&account_id).then(Self::registered_balance)
+    }
+
+    pub fn storage_balance_bounds(&self) -> StorageBalanceBounds {
+        StorageBalanceBounds {
+            min: U128(STORAGE_BALANCE_MIN),
+            max: Some(U128(STORAGE_BALANCE_MIN)),
+        }
+    }
+}
+
+impl Stub {
+    fn registered_balance() -> StorageBalance {
+        StorageBalance {
+            total: U128(STORAGE_BALANCE_MIN),
+            available: U128(0),
+        }
+    }
+}
@@ -0,0 +1,231 @@
+use crate::*;
+
+/// index of the rules entry for a receiver, entries like "*.app.near" match any sub-account of app.near
+/// an exact entry always wins, otherwise the longest (most specific) matching suffix
+pub(crate) fn find_contract(contracts: &[&str], receiver_id: &str) -> Option<usize> {
+    if let Some(index) = contracts.iter().position(|c| *c == receiver_id) {
+        return Some(index);
+    }
+    contracts
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| c.starts_with(SUB_ACCOUNT_PREFIX) && contract_matches(c, receiver_id))
+        .max_by_key(|(_, c)| c.len())
+        .map(|(index, _)| index)
+}
+
+/// exact account or "*.parent" matching any sub-account of parent
+pub(crate) fn contract_matches(pattern: &str, receiver_id: &str) -> bool {
+    match pattern.strip_prefix(SUB_ACCOUNT_PREFIX) {
+        Some(parent) => receiver_id.len() > parent.len() + 1
+            && receiver_id.ends_with(parent)
+            && receiver_id.as_bytes()[receiver_id.len() - parent.len() - 1] == b'.',
+        None => pattern == receiver_id,
+    }
+}
+
+/// methods allowed for a contract, entries may be globs with a single '*' (e.g. "nft_*", "*_view", "*")
+/// and entries prefixed with '!' deny matching methods, taking precedence over any allow
+pub(crate) fn method_allowed(patterns: &[&str], method_name: &str) -> bool {
+    let mut allowed = false;
+    for pattern in patterns {
+        match pattern.strip_prefix(DENY_PREFIX) {
+            Some(denied) => {
+                if glob_match(denied, method_name) {
+                    return false;
+                }
+            }
+            None => allowed = allowed || glob_match(pattern, method_name),
+        }
+    }
+    allowed
+}
+
+/// match a value against a pattern containing at most one '*'
+pub(crate) fn glob_match(pattern: &str, value: &str) -> bool {
+    match pattern.split_once(ANY_METHOD) {
+        Some((prefix, suffix)) => {
+            value.len() >= prefix.len() + suffix.len() && value.starts_with(prefix) && value.ends_with(suffix)
+        }
+        None => pattern == value,
+    }
+}
+
+/// argument constraints from the optional "arg_constraints" rule
+/// entries are separated by ';' as "contract:method:field:op[:value]" where contract may be "*.parent" and method a glob
+/// ops: "req" field present, "eq" exact value, "in" one of '|' separated values, "max" numeric maximum
+/// every op fails when the field is missing from the args
+/// values may use the placeholders "$current_account_id" and "$funder" (see substitute_placeholders)
+pub(crate) fn assert_args_allowed(rules_str: &str, receiver_id: &str, method_name: &str, args: &str) {
+    let constraints = match get_string_opt(rules_str, "|kP|arg_constraints") {
+        Some(constraints) => constraints,
+        None => return,
+    };
+
+    for constraint in constraints.split(';') {
+        let parts: Vec<&str> = constraint.splitn(5, ':').collect();
+        if parts.len() < 4 {
+            sys::panic()
+        }
+        let (contract_id, method, field, op) = (parts[0], parts[1], parts[2], parts[3]);
+        if !contract_matches(contract_id, receiver_id) || !glob_match(method, method_name) {
+            continue;
+        }
+        let expected = substitute_placeholders(rules_str, parts.get(4).copied().unwrap_or(""));
+        let expected = expected.as_str();
+
+        let allowed = match get_json_field(args, field).map(unquote) {
+            None => false,
+            Some(value) => match op {
+                "req" => true,
+                "eq" => value == expected,
+                "in" => expected.split('|').any(|e| e == value),
+                "max" => match value.parse::<u128>() {
+                    Ok(value) => value <= expected.parse::<u128>().ok().unwrap_or_else(|| sys::panic()),
+                    Err(_) => false,
+                },
+                _ => sys::panic(),
+            },
+        };
+        if !allowed {
+            log(&format!("arg constraint failed: {}", constraint));
+            sys::panic()
+        }
+    }
+}
+
+/// replace placeholders so rules can refer to accounts unknown when the drop was created
+pub(crate) fn substitute_placeholders(rules_str: &str, value: &str) -> String {
+    let mut ret = value.to_string();
+    if ret.contains(CURRENT_ACCOUNT_PLACEHOLDER) {
+        ret = ret.replace(CURRENT_ACCOUNT_PLACEHOLDER, &sys_account_id(0));
+    }
+    if ret.contains(FUNDER_PLACEHOLDER) {
+        ret = ret.replace(FUNDER_PLACEHOLDER, get_string(rules_str, "|kP|funder"));
+    }
+    ret
+}
+
+/// strip the quotes of a raw json string value
+fn unquote(value: &str) -> &str {
+    if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+        return &value[1..value.len() - 1];
+    }
+    value
+}
+
+/// NFTs on contracts in the optional "nft_locked" rule (comma separated) cannot leave the account
+/// until the exit conditions checked by can_exit are met, other methods on those contracts still work
+pub(crate) fn assert_nft_unlocked(rules_str: &str, receiver_id: &str, method_name: &str) {
+    if !NFT_LOCKED_METHODS.contains(&method_name) {
+        return;
+    }
+    let locked = get_string_opt(rules_str, "|kP|nft_locked").unwrap_or("");
+    if !locked.split(',').any(|c| contract_matches(c, receiver_id)) {
+        return;
+    }
+    if !exit_status(rules_str).failing.is_empty() {
+        log("nft locked until exit");
+        sys::panic()
+    }
+}
+
+/// max deposit for the automatic registration from the optional "storage_deposit" rule
+/// as "contract:max_deposit,..." where contract may be "*.parent", picked with the precedence of find_contract
+pub(crate) fn get_storage_deposit(rules_str: &str, receiver_id: &str) -> Option<u128> {
+    let entries: Vec<(&str, &str)> = get_string_opt(rules_str, "|kP|storage_deposit")?
+        .split(',')
+        .map(|c| split_once(c, ":"))
+        .collect();
+    let contracts: Vec<&str> = entries.iter().map(|(contract_id, _)| *contract_id).collect();
+    let index = find_contract(&contracts, receiver_id)?;
+    Some(entries[index].1.parse().ok().unwrap_or_else(|| sys::panic()))
+}
+
+/// fungible token allowances from the optional "ft_allowances" rule as "token:per_call:total;..."
+/// where token may be "*.parent", the spent total is tracked per entry
+pub(crate) fn get_ft_allowances_rule(rules_str: &str) -> Vec<(&str, u128, u128)> {
+    let allowances = match get_string_opt(rules_str, "|kP|ft_allowances") {
+        Some(allowances) => allowances,
+        None => return vec![],
+    };
+    allowances
+        .split(';')
+        .map(|a| {
+            let parts: Vec<&str> = a.split(':').collect();
+            if parts.len() != 3 {
+                sys::panic()
+            }
+            let per_call: u128 = parts[1].parse().ok().unwrap_or_else(|| sys::panic());
+            let total: u128 = parts[2].parse().ok().unwrap_or_else(|| sys::panic());
+            (parts[0], per_call, total)
+        })
+        .collect()
+}
+
+/// the allowance entry covering a token, with the same precedence as find_contract
+fn ft_allowance_for<'a>(allowances: &[(&'a str, u128, u128)], receiver_id: &str) -> Option<(&'a str, u128, u128)> {
+    let tokens: Vec<&str> = allowances.iter().map(|(t, _, _)| *t).collect();
+    find_contract(&tokens, receiver_id).map(|index| allowances[index])
+}
+
+/// cumulative amount spent per allowance entry
+pub(crate) fn get_ft_spent() -> Vec<(String, u128)> {
+    if !storage_has_key(FT_SPENT_KEY) {
+        return vec![];
+    }
+    storage_read_str(FT_SPENT_KEY)
+        .split(',')
+        .map(|s| {
+            let (token_id, amount) = split_once(s, ":");
+            (token_id.to_string(), amount.parse().ok().unwrap_or_else(|| sys::panic()))
+        })
+        .collect()
+}
+
+pub(crate) fn ft_spent_for(spent: &[(String, u128)], token_id: &str) -> u128 {
+    spent.iter().find(|(t, _)| t == token_id).map(|(_, amount)| *amount).unwrap_or(0)
+}
+
+fn write_ft_spent(token_id: &str, amount: u128) {
+    let mut spent = get_ft_spent();
+    match spent.iter_mut().find(|(t, _)| t == token_id) {
+        Some((_, spent_amount)) => *spent_amount = amount,
+        None => spent.push((token_id.to_string(), amount)),
+    }
+    let spent: Vec<String> = spent.iter().map(|(t, a)| format!("{}:{}", t, a)).collect();
+    swrite(FT_SPENT_KEY, spent.join(",").as_bytes());
+}
+
+/// enforce per call and cumulative caps for ft_transfer(_call) on allowlisted tokens
+/// the amount is counted as spent right away and returned so the callback can refund a failed transfer
+pub(crate) fn reserve_ft_allowance(rules_str: &str, receiver_id: &str, method_name: &str, args: &str) -> u128 {
+    if !FT_TRANSFER_METHODS.contains(&method_name) {
+        return 0;
+    }
+    let (token_id, per_call, total) = match ft_allowance_for(&get_ft_allowances_rule(rules_str), receiver_id) {
+        Some(allowance) => allowance,
+        None => return 0,
+    };
+
+    let amount: u128 = get_json_field(args, "amount")
+        .map(unquote)
+        .and_then(|a| a.parse().ok())
+        .unwrap_or_else(|| sys::panic());
+    let spent = ft_spent_for(&get_ft_spent(), token_id) + amount;
+    if amount > per_call || spent > total {
+        log("ft allowance exceeded");
+        sys::panic()
+    }
+    write_ft_spent(token_id, spent);
+    amount
+}
+
+pub(crate) fn refund_ft_allowance(rules_str: &str, receiver_id: &str, amount: u128) {
+    let token_id = match ft_allowance_for(&get_ft_allowances_rule(rules_str), receiver_id) {
+        Some((token_id, _, _)) => token_id,
+        None => return,
+    };
+    let spent = ft_spent_for(&get_ft_spent(), token_id);
+    write_ft_spent(token_id, spent.saturating_sub(amount));
+}
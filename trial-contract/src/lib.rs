@@ -4,6 +4,14 @@
 /// storage keys used by this contract because it uses raw storage key value writes and reads
 const RULES_KEY: &[u8] = b"r";
 const FLOOR_KEY: &[u8] = b"f";
+const USAGE_KEY: &[u8] = b"u";
+const PAUSED_KEY: &[u8] = b"p";
+const SETUP_KEY: &[u8] = b"s";
+/// fungible tokens spent so far as "token:amount,..."
+const FT_SPENT_KEY: &[u8] = b"t";
+/// comma separated base58 hashes of contracts staged by the funder, code is stored under CODE_KEY_PREFIX + hash
+const STAGED_KEY: &[u8] = b"h";
+const CODE_KEY_PREFIX: &[u8] = b"c";
 /// register constants used
 const REGISTER_0: u64 = 0;
 /// string literals (improve readability)
@@ -13,19 +21,68 @@ const ACTION_HEADER: &str = "\"|kA|\":";
 const PARAM_STOP: &str = "|kS|\"";
 const COMMA: &str = ",";
 const ANY_METHOD: &str = "*";
+const DENY_PREFIX: &str = "!";
+const SUB_ACCOUNT_PREFIX: &str = "*.";
+/// fungible token methods limited by "ft_allowances"
+const FT_TRANSFER_METHODS: [&str; 2] = ["ft_transfer", "ft_transfer_call"];
+/// nft methods that move or approve tokens, blocked by "nft_locked"
+const NFT_LOCKED_METHODS: [&str; 6] = ["nft_transfer", "nft_transfer_call", "nft_transfer_payout", "nft_approve", "nft_batch_transfer", "nft_batch_approve"];
+/// placeholders in rule values, replaced by the trial account and funder ids
+const CURRENT_ACCOUNT_PLACEHOLDER: &str = "$current_account_id";
+const FUNDER_PLACEHOLDER: &str = "$funder";
 const CALLBACK_GAS: u64 = 20_000_000_000_000;
+/// gas for the storage_deposit prepended by the "storage_deposit" rule
+const STORAGE_DEPOSIT_GAS: u64 = 10_000_000_000_000;
+/// gas for the optional external view checked before exit
+const EXIT_CHECK_GAS: u64 = 10_000_000_000_000;
+/// gas a client should attach to execute, reported by get_key_information
+const REQUIRED_GAS: u64 = 50_000_000_000_000;
 const YOCTO_PER_GAS_UNIT: u128 = 100_000_000;
+const STORAGE_BYTE_COST: u128 = 10_000_000_000_000_000_000;
+
+/// rules describing how much is repaid, these cannot change once rules are locked
+const REPAY_RULES: [&str; 7] = [
+	"|kP|repay_schedule",
+	"|kP|repay_end",
+	"|kP|repay_duration",
+	"|kP|repay_steps",
+	"|kP|repay_percent",
+	"|kP|repay_combine",
+	"|kP|repay_split",
+];
+/// external exit check, once locked it may only be removed
+const EXIT_CHECK_RULES: [&str; 3] = ["|kP|exit_check_contract", "|kP|exit_check_method", "|kP|exit_check_args"];
+/// exit conditions "grace_relax" may waive during the grace period
+const GRACE_RELAXABLE: [&str; 4] = ["floor", "calls", "methods", "days"];
+const NS_PER_MS: u64 = 1_000_000;
+const NS_PER_DAY: u64 = 86_400_000_000_000;
+const BASIS_POINTS: u128 = 10_000;
+
+/// rules the funder may change one at a time via update_rules
+const UPDATABLE_RULES: [&str; 4] = ["repay", "floor", "expiry", "grace_period"];
+
+/// NEP-297 event metadata
+const EVENT_STANDARD: &str = "trial_accounts";
+const EVENT_VERSION: &str = "1.0.0";
 
 /// repeated string literals (in parsing tx payloads)
 const DEPOSIT: &str = "|kP|deposit";
 const CALLBACK_METHOD_NAME: &str = "callback";
+const EXIT_CHECK_CALLBACK_METHOD_NAME: &str = "exit_check_callback";
+/// method names must not contain the separators used by the usage record and callback args
+const USAGE_SEPARATORS: [char; 4] = [':', ';', ',', '"'];
+/// distinct contract and method pairs tracked in the usage record
+const MAX_USAGE_CALLS: usize = 32;
+/// exit modes, chosen by the entry point and carried through the exit check
+const CLAIM_MODE: &str = "claim";
+const EXIT_TO_MODE: &str = "exit_to";
+const STORAGE_DEPOSIT_METHOD_NAME: &str = "storage_deposit";
 
 extern crate alloc;
 
-/// DEBUGGING REMOVE
+// DEBUGGING REMOVE
 // use alloc::format;
 
-
 use alloc::vec;
 use alloc::vec::Vec;
 use alloc::format;
@@ -37,6 +94,10 @@ mod sys;
 use sys::*;
 mod parse;
 use parse::*;
+mod usage;
+use usage::*;
+mod constraints;
+use constraints::*;
 
 #[cfg(target_arch = "wasm32")]
 #[global_allocator]
@@ -59,38 +120,206 @@ pub unsafe fn on_alloc_error(_: core::alloc::Layout) -> ! {
 #[no_mangle]
 pub fn setup() {
 	// check if funder is calling to switch rules, if not funder and rules set, panic
-	unsafe {
-		if near_sys::storage_has_key(RULES_KEY.len() as u64, RULES_KEY.as_ptr() as u64) == 1 {
-			let rules_str = storage_read_str(RULES_KEY);
-			let funder = get_string(&rules_str, "|kP|funder").to_string();
-			let predecessor_account_id = sys_account_id(1);
-			if funder != predecessor_account_id {
-				log("only funder");
+	let mut keep_floor = false;
+	let input_str = get_input(true);
+	if storage_has_key(RULES_KEY) {
+		let rules_str = storage_read_str(RULES_KEY);
+		assert_funder(&rules_str);
+		assert_no_rug(&rules_str, &input_str);
+		// resetting the floor would wipe the user's spend towards exit
+		keep_floor = is_rules_locked(&rules_str);
+	}
+	assert_valid_rules(&input_str);
+
+	swrite(RULES_KEY, input_str.as_bytes());
+	swrite(SETUP_KEY, &block_timestamp().to_le_bytes());
+	if !keep_floor {
+		let floor = account_balance();
+		swrite(FLOOR_KEY, &floor.to_le_bytes());
+	}
+}
+
+#[no_mangle]
+pub fn update_rules() {
+	let mut rules_str = storage_read_str(RULES_KEY);
+	assert_funder(&rules_str);
+
+	let input_str = get_input(true);
+	// every change is recorded as {"rule","old","new"} for the event
+	let mut changes: Vec<String> = vec![];
+
+	for rule in UPDATABLE_RULES {
+		if let Some(value) = get_json_string(&input_str, rule) {
+			// reject values the other entry points would fail to parse
+			let valid = match rule {
+				"expiry" | "grace_period" => value.parse::<u64>().is_ok(),
+				_ => value.parse::<u128>().is_ok(),
+			};
+			if !valid {
+				log("invalid rule value");
 				sys::panic();
 			}
+			rules_str = update_rule(&rules_str, rule, value, &mut changes);
 		}
 	}
-	
-    let input_str = get_input(true);
-	swrite(RULES_KEY, input_str.as_bytes());
-	let floor = account_balance();
-    swrite(FLOOR_KEY, &floor.to_le_bytes());
+
+	// contract allowances are parallel lists, rebuild all three together
+	let remove_contract = get_json_string(&input_str, "remove_contract");
+	let set_contract = get_json_string(&input_str, "set_contract");
+	if remove_contract.is_some() || set_contract.is_some() {
+		let (contracts, methods, amounts) = get_allowances(&rules_str);
+		let mut contracts: Vec<String> = contracts.iter().map(|c| c.to_string()).collect();
+		let mut methods: Vec<String> = methods.iter().map(|m| m.join(":")).collect();
+		let mut amounts: Vec<String> = amounts.iter().map(|a| a.to_string()).collect();
+
+		if let Some(contract_id) = remove_contract {
+			let index = contracts.iter().position(|c| c == contract_id).unwrap_or_else(|| sys::panic());
+			contracts.remove(index);
+			methods.remove(index);
+			amounts.remove(index);
+			if contracts.is_empty() {
+				log("cannot remove last contract");
+				sys::panic();
+			}
+		}
+
+		if let Some(contract_id) = set_contract {
+			let contract_methods = get_json_string(&input_str, "methods").unwrap_or_else(|| sys::panic());
+			let amount = get_json_string(&input_str, "amount").unwrap_or_else(|| sys::panic());
+			amount.parse::<u128>().ok().unwrap_or_else(|| sys::panic());
+			// values end up in the comma separated rule lists, methods are ':' separated within them
+			let valid_contract = is_valid_account_id(contract_id.strip_prefix(SUB_ACCOUNT_PREFIX).unwrap_or(contract_id));
+			let valid_methods = contract_methods
+				.split(':')
+				.all(|m| !m.is_empty() && !m.contains([',', '|', '"', '\\']));
+			if !valid_contract || !valid_methods {
+				log("invalid contract");
+				sys::panic();
+			}
+			match contracts.iter().position(|c| c == contract_id) {
+				Some(index) => {
+					methods[index] = contract_methods.to_string();
+					amounts[index] = amount.to_string();
+				}
+				None => {
+					contracts.push(contract_id.to_string());
+					methods.push(contract_methods.to_string());
+					amounts.push(amount.to_string());
+				}
+			}
+		}
+
+		rules_str = update_rule(&rules_str, "contracts", &contracts.join(","), &mut changes);
+		rules_str = update_rule(&rules_str, "methods", &methods.join(","), &mut changes);
+		rules_str = update_rule(&rules_str, "amounts", &amounts.join(","), &mut changes);
+	}
+
+	assert_no_rug(&storage_read_str(RULES_KEY), &rules_str);
+	assert_valid_rules(&rules_str);
+	// floor and usage are left untouched, unlike setup
+	swrite(RULES_KEY, rules_str.as_bytes());
+	emit_event("update_rules", &format!("{{\"changes\":[{}]}}", changes.join(",")));
+}
+
+/// funder stages contract code (raw wasm as input) the user may choose to deploy on exit
+#[no_mangle]
+pub fn stage_contract() {
+	assert_funder(&storage_read_str(RULES_KEY));
+
+	let code = get_input_bytes();
+	// the funder pays for the code kept in trial storage, refunded by unstage_contract
+	if attached_deposit() < code.len() as u128 * STORAGE_BYTE_COST {
+		log("insufficient storage deposit");
+		sys::panic();
+	}
+	let hash = base58_encode(&sha256(&code));
+	swrite(&code_key(&hash), &code);
+
+	let mut staged = get_staged_contracts();
+	if !staged.contains(&hash) {
+		staged.push(hash.clone());
+		swrite(STAGED_KEY, staged.join(",").as_bytes());
+	}
+	emit_event("stage_contract", &format!("{{\"hash\":\"{}\",\"size\":{}}}", hash, code.len()));
+}
+
+/// funder removes a staged contract by its hash, the storage deposit is returned
+#[no_mangle]
+pub fn unstage_contract() {
+	let funder = assert_funder(&storage_read_str(RULES_KEY));
+
+	let input_str = get_input(true);
+	let hash = get_json_string(&input_str, "hash").unwrap_or_else(|| sys::panic());
+	let mut staged = get_staged_contracts();
+	let index = staged.iter().position(|h| h == hash).unwrap_or_else(|| {
+		log("contract not staged");
+		sys::panic()
+	});
+	let size = storage_read(&code_key(hash)).len();
+	storage_remove(&code_key(hash));
+	staged.remove(index);
+	if staged.is_empty() {
+		storage_remove(STAGED_KEY);
+	} else {
+		swrite(STAGED_KEY, staged.join(",").as_bytes());
+	}
+
+	let refund = size as u128 * STORAGE_BYTE_COST;
+	let refund_id = create_promise_batch(funder, None);
+	unsafe {
+		near_sys::promise_batch_action_transfer(
+			refund_id,
+			refund.to_le_bytes().as_ptr() as u64,
+		)
+	}
+	emit_event("unstage_contract", &format!("{{\"hash\":\"{}\",\"size\":{}}}", hash, size));
+}
+
+// pause and resume would clash with libc symbols when linking the host build
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub fn pause() {
+	let rules_str = storage_read_str(RULES_KEY);
+	let funder = assert_funder(&rules_str);
+	// pausing would block the user from using locked rules for as long as the funder likes
+	if is_rules_locked(&rules_str) {
+		log("rules locked");
+		sys::panic();
+	}
+
+	let input_str = get_input(false);
+	// kept json escaped so the event and the status view stay valid json
+	let reason = get_json_string(&input_str, "reason").unwrap_or("");
+	swrite(PAUSED_KEY, reason.as_bytes());
+	emit_event("pause", &format!("{{\"funder\":\"{}\",\"reason\":\"{}\"}}", funder, reason));
+}
+
+#[cfg_attr(target_arch = "wasm32", no_mangle)]
+pub fn resume() {
+	let rules_str = storage_read_str(RULES_KEY);
+	let funder = assert_funder(&rules_str);
+
+	storage_remove(PAUSED_KEY);
+	emit_event("resume", &format!("{{\"funder\":\"{}\"}}", funder));
 }
 
 #[no_mangle]
 pub fn execute() {
+	assert_not_paused();
 
 	let rules_str = &storage_read_str(RULES_KEY);
+	if is_expired(get_expiry(rules_str)) {
+		log("trial expired");
+		sys::panic();
+	}
+	// still usable, but warn apps so they can prompt the user to claim
+	if let Some(time_remaining) = grace_time_remaining(rules_str) {
+		emit_event("expiry_warning", &format!("{{\"time_remaining\":\"{}\"}}", time_remaining));
+	}
 
-	let contracts: Vec<&str> = get_string(rules_str, "|kP|contracts").split(",").collect();
-	let methods: Vec<Vec<&str>> = get_string(rules_str, "|kP|methods").split(",").map(|s| s.split(":").collect()).collect();
-	let amounts: Vec<u128> = get_string(rules_str, "|kP|amounts")
-		.split(",")
-		.map(|a| {
-			let amount: u128 = a.parse().ok().unwrap_or_else(|| sys::panic());
-			amount
-		})
-		.collect();
+	let (contracts, methods, amounts) = get_allowances(rules_str);
+
+	let mut usage = Usage::read();
+	usage.record_execute();
 
 	// args
 	let input_str = get_input(true);
@@ -103,23 +332,23 @@ pub fn execute() {
 	let mut promises: Vec<u64> = vec![];
 
 	// execute transactions
-	while transactions.len() > 0 {
+	while !transactions.is_empty() {
 		let tx = transactions.remove(0);
 
 		let (receiver_id_str, tx_rest) = split_once(tx, COMMA);
 		let receiver_id = receiver_id_str[1..receiver_id_str.len()-1].to_string();
 		// log(receiver_id);
 
-		let receiver_index_option = contracts.iter().position(|c| c == &receiver_id);
+		let receiver_index_option = find_contract(&contracts, &receiver_id);
 		if receiver_index_option.is_none() {
 			sys::panic()
 		}
 		let receiver_index = receiver_index_option.unwrap();
 
-		let id = if promises.len() == 0 {
-			create_promise_batch(receiver_id, None)
+		let id = if promises.is_empty() {
+			create_promise_batch(receiver_id.clone(), None)
 		} else {
-			create_promise_batch(receiver_id, Some(promises[promises.len() - 1]))
+			create_promise_batch(receiver_id.clone(), Some(promises[promises.len() - 1]))
 		};
 		promises.push(id);
 
@@ -128,8 +357,18 @@ pub fn execute() {
 		actions.remove(0);
 		let mut action_gas = 0;
 		let mut action_deposits: u128 = 0;
+		let mut action_methods: Vec<&str> = vec![];
+		let mut action_ft_amount: u128 = 0;
+
+		// register the trial account first on NEP-145 contracts flagged in the rules
+		// the bounded deposit is refunded by the contract when already registered, so it does not count against the floor
+		if let Some(deposit) = get_storage_deposit(rules_str, &receiver_id) {
+			let args = format!("{{\"account_id\":\"{}\",\"registration_only\":true}}", sys_account_id(0));
+			promise_function_call(id, STORAGE_DEPOSIT_METHOD_NAME, &args, deposit, STORAGE_DEPOSIT_GAS);
+			action_gas += STORAGE_DEPOSIT_GAS;
+		}
 		
-		while actions.len() > 0 {
+		while !actions.is_empty() {
 			let action = actions.remove(0);
 
 			let (mut action_type, params) = split_once(action, COMMA);
@@ -140,37 +379,44 @@ pub fn execute() {
 			// TODO do we support NEAR transfers?
 
 
-			match action_type.as_bytes() {
-				b"FunctionCall" => {
-					let method_name = get_string(params, "|kP|methodName");
-
-					if methods[receiver_index][0] != ANY_METHOD && !methods[receiver_index].contains(&method_name) {
-						sys::panic()
-					}
-					let args = &get_string(params, "|kP|args")
-						.replace("\\\\", "\\");
-					let deposit = get_u128(params, DEPOSIT);
-					// check if deposit exceeds allowed limit for function calls for this contract
-					if deposit > amounts[receiver_index] {
-						sys::panic()
-					}
-					action_deposits += deposit;
-					let gas = get_u128(params, "|kP|gas") as u64;
-					action_gas += gas;
-					
-					unsafe {
-						near_sys::promise_batch_action_function_call(
-							id,
-							method_name.len() as u64,
-							method_name.as_ptr() as u64,
-							args.len() as u64,
-							args.as_ptr() as u64,
-							deposit.to_le_bytes().as_ptr() as u64,
-							gas,
-						);
-					};
+			if action_type == "FunctionCall" {
+				let method_name = get_string(params, "|kP|methodName");
+				// separators would corrupt the stored usage and the callback args
+				if method_name.contains(USAGE_SEPARATORS) {
+					log("invalid method name");
+					sys::panic()
+				}
+
+				if !method_allowed(&methods[receiver_index], method_name) {
+					sys::panic()
 				}
-				_ => {}
+				usage.record_call(&receiver_id, method_name);
+				action_methods.push(method_name);
+				let args = &get_string(params, "|kP|args")
+					.replace("\\\\", "\\");
+				assert_args_allowed(rules_str, &receiver_id, method_name, args);
+				assert_nft_unlocked(rules_str, &receiver_id, method_name);
+				action_ft_amount += reserve_ft_allowance(rules_str, &receiver_id, method_name, args);
+				let deposit = get_u128(params, DEPOSIT);
+				// check if deposit exceeds allowed limit for function calls for this contract
+				if deposit > amounts[receiver_index] {
+					sys::panic()
+				}
+				action_deposits += deposit;
+				let gas = get_u128(params, "|kP|gas") as u64;
+				action_gas += gas;
+				
+				unsafe {
+					near_sys::promise_batch_action_function_call(
+						id,
+						method_name.len() as u64,
+						method_name.as_ptr() as u64,
+						args.len() as u64,
+						args.as_ptr() as u64,
+						deposit.to_le_bytes().as_ptr() as u64,
+						gas,
+					);
+				};
 			}
 		}
 
@@ -179,8 +425,17 @@ pub fn execute() {
 			let cb_id = create_promise_batch(sys_account_id(0), Some(id));
 			promises.push(cb_id);
 			// all deposits and gas attached to actions count against the floor and used gas up to this call (ignore callback gas)
+			// receiver and methods are passed along so successful calls can be counted
+			// and reserved fungible token amounts so failed transfers can be refunded
 			let callback_deposit: u64 = 0;
-			let args = format!("{},{}", action_deposits, action_gas + near_sys::used_gas());
+			let args = format!(
+				"{},{},{},{},{}",
+				action_deposits,
+				action_gas + near_sys::used_gas(),
+				receiver_id,
+				action_methods.join(":"),
+				action_ft_amount,
+			);
 			near_sys::promise_batch_action_function_call(
 				cb_id,
 				CALLBACK_METHOD_NAME.len() as u64,
@@ -192,13 +447,34 @@ pub fn execute() {
 			);
 		}
 	}
+
+	usage.write();
 }
 
+/// # Safety
+/// only called by this contract as the final promise of each transaction in `execute`
 #[no_mangle]
 pub unsafe fn callback() {
 	// log(CALLBACK_METHOD_NAME);
+	// args drive the floor, usage and ft allowance counters so only this contract may call back
+	assert_self();
+
+	// parse the attachedDeposit from the call
+    let input_str = get_input(false);
+	let parts: Vec<&str> = input_str.splitn(5, ",").collect();
+	if parts.len() != 5 {
+		sys::panic()
+	}
+	let (attached_deposit_str, prepaid_gas_str, receiver_id, methods) = (parts[0], parts[1], parts[2], parts[3]);
+	let ft_amount: u128 = parts[4].parse().ok().unwrap_or_else(|| sys::panic());
 
-	near_sys::promise_result(0, REGISTER_0);
+	if near_sys::promise_result(0, REGISTER_0) != 1 {
+		// tokens never left the account, give the allowance back
+		if ft_amount > 0 {
+			refund_ft_allowance(&storage_read_str(RULES_KEY), receiver_id, ft_amount);
+		}
+		return log("promise failed");
+	}
 	let result_bytes = register_read(REGISTER_0);
 	let result = alloc::str::from_utf8(&result_bytes).ok().unwrap_or_else(|| sys::panic());
 	
@@ -206,9 +482,6 @@ pub unsafe fn callback() {
 		return log("promise false");
 	}
 
-	// parse the attachedDeposit from the call
-    let input_str = get_input(false);
-	let (attached_deposit_str, prepaid_gas_str) = split_once(&input_str, ",");
 	let attached_deposit: u128 = attached_deposit_str.parse().ok().unwrap_or_else(|| sys::panic());
 	let prepaid_gas: u128 = prepaid_gas_str.parse().ok().unwrap_or_else(|| sys::panic());
 	let gas_cost = prepaid_gas * YOCTO_PER_GAS_UNIT;
@@ -217,30 +490,103 @@ pub unsafe fn callback() {
 	let mut floor = get_floor();
 	floor = floor - attached_deposit - gas_cost;
     swrite(FLOOR_KEY, &floor.to_le_bytes());
+
+	// update usage totals
+	let mut usage = Usage::read();
+	usage.deposit += attached_deposit;
+	usage.gas += prepaid_gas;
+	for method_name in methods.split(":").filter(|m| !m.is_empty()) {
+		usage.record_success(receiver_id, method_name);
+	}
+	usage.write();
 }
 
-fn can_exit(rules_str: &str) -> Option<u128> {
+/// snapshot of every exit condition, used by can_exit and the get_exit_status view
+struct ExitStatus {
+	balance: u128,
+	repay: u128,
+	floor: u128,
+	floor_exit: u128,
+	expiry: Option<u64>,
+	referral_reward: u128,
+	usage: Usage,
+	failing: Vec<&'static str>,
+}
+
+fn exit_status(rules_str: &str) -> ExitStatus {
 	// rules
-	let repay: u128 = get_u128(rules_str, "|kP|repay");
+	let balance = account_balance();
+	let repay: u128 = get_exit_repay(rules_str, balance);
 	let floor_exit: u128 = get_u128(rules_str, "|kP|floor");
-	let account_balance = account_balance();
-	// log(&format!("repay: {}", repay));
-	// log(&format!("floor_exit: {}", floor_exit));
-	// log(&format!("account_balance: {}", account_balance));
-	// repay
-	if account_balance < repay {
-		log("cannot repay");
-		return None;
+	let expiry = get_expiry(rules_str);
+	let floor = get_floor();
+	let referral_reward = get_referral(rules_str).map(|(_, reward)| reward).unwrap_or(0);
+
+	let usage = Usage::read();
+
+	let mut failing = vec![];
+	// expiry
+	if is_expired(expiry) {
+		failing.push("expiry");
+	}
+	// repay, the referral reward is paid from the same balance
+	if balance < repay + referral_reward {
+		failing.push("repay");
 	}
 	// floor
-	let floor = get_floor();
-	// log(&format!("floor: {}", floor));
 	if floor > floor_exit {
+		failing.push("floor");
+	}
+
+	// activity, each predicate is optional
+	if let Some(calls) = get_string_opt(rules_str, "|kP|exit_calls") {
+		if usage.successful_calls < calls.parse().ok().unwrap_or_else(|| sys::panic()) {
+			failing.push("calls");
+		}
+	}
+	if let Some(methods) = get_string_opt(rules_str, "|kP|exit_methods") {
+		let used = methods.split(",").all(|m| {
+			let (contract_id, method_name) = split_once(m, ":");
+			usage.method_succeeded(contract_id, method_name)
+		});
+		if !used {
+			failing.push("methods");
+		}
+	}
+	if let Some(days) = get_string_opt(rules_str, "|kP|exit_days") {
+		if usage.active_days < days.parse().ok().unwrap_or_else(|| sys::panic()) {
+			failing.push("days");
+		}
+	}
+
+	// during the grace period before expiry some conditions may be waived
+	if grace_time_remaining(rules_str).is_some() {
+		let relaxed: Vec<&str> = get_string_opt(rules_str, "|kP|grace_relax").unwrap_or("").split(",").collect();
+		failing.retain(|f| !GRACE_RELAXABLE.contains(f) || !relaxed.contains(f));
+	}
+
+	ExitStatus { balance, repay, floor, floor_exit, expiry, referral_reward, usage, failing }
+}
+
+fn can_exit(rules_str: &str) -> Option<u128> {
+	let status = exit_status(rules_str);
+	if status.failing.contains(&"expiry") {
+		log("trial expired");
+	}
+	if status.failing.contains(&"repay") {
+		log("cannot repay");
+	}
+	if status.failing.contains(&"floor") {
 		log("floor > floor_exit");
+	}
+	if status.failing.contains(&"calls") || status.failing.contains(&"methods") || status.failing.contains(&"days") {
+		log("not enough activity");
+	}
+	if !status.failing.is_empty() {
 		return None;
 	}
 
-	Some(repay)
+	Some(status.repay)
 }
 
 #[no_mangle]
@@ -248,71 +594,606 @@ pub fn create_account_and_claim() {
 	let rules_str = storage_read_str(RULES_KEY);
 	let funder = get_string(&rules_str, "|kP|funder").to_string();
 	let predecessor_account_id = sys_account_id(1);
+	let input_str = get_input(true);
+
+	// allow funder to claim trial with new full access key when the takeover policy allows it
+	if funder == predecessor_account_id {
+		if !takeover_allowed(&rules_str) {
+			log("takeover not allowed");
+			sys::panic();
+		}
+		return claim(&input_str, None);
+	}
+
+	// user cannot exit a paused trial, funder can still take it over
+	assert_not_paused();
 
-	// allow funder to claim trial with new full access key at any time
-	let refund_id = if funder != predecessor_account_id {
-		// non-funder e.g. user will have to pass exit conditions
-		let exit_option = can_exit(&rules_str);
-		let repay = exit_option.unwrap_or_else(|| sys::panic());
+	if !start_exit_check(&rules_str, CLAIM_MODE) {
+		user_exit(&rules_str, funder, CLAIM_MODE, &input_str);
+	}
+}
 
-		// non-funder will have to repay funder from this account
-		let refund_id = create_promise_batch(funder, None);
+/// exit to an existing account: repay, then send the rest (or delete the trial with the account as beneficiary)
+#[no_mangle]
+pub fn exit_to_account() {
+	// only the trial access key may move the funds out
+	assert_trial_key();
+	let rules_str = storage_read_str(RULES_KEY);
+	let funder = get_string(&rules_str, "|kP|funder").to_string();
+	let input_str = get_input(true);
+	let account_id = get_json_string(&input_str, "account_id").unwrap_or_else(|| sys::panic());
+	if !is_valid_account_id(account_id) || account_id == sys_account_id(0) {
+		log("invalid account_id");
+		sys::panic();
+	}
+
+	assert_not_paused();
+
+	if !start_exit_check(&rules_str, EXIT_TO_MODE) {
+		user_exit(&rules_str, funder, EXIT_TO_MODE, &input_str);
+	}
+}
+
+/// user gives the trial back, the account is deleted with the funder as beneficiary
+#[no_mangle]
+pub fn forfeit() {
+	// only the trial access key may forfeit
+	assert_trial_key();
+	let rules_str = storage_read_str(RULES_KEY);
+	let funder = get_string(&rules_str, "|kP|funder");
+
+	let forfeit_id = create_promise_batch(sys_account_id(0), None);
+	unsafe {
+		near_sys::promise_batch_action_delete_account(
+			forfeit_id,
+			funder.len() as u64,
+			funder.as_ptr() as u64,
+		);
+	}
+	emit_event("forfeit", &format!("{{\"funder\":\"{}\",\"amount\":\"{}\"}}", funder, account_balance()));
+}
+
+/// an external check must pass first, the exit then continues in exit_check_callback
+/// returns false when the rules have no exit check
+fn start_exit_check(rules_str: &str, mode: &str) -> bool {
+	let contract_id = match get_string_opt(rules_str, "|kP|exit_check_contract") {
+		Some(contract_id) => contract_id,
+		None => return false,
+	};
+	// fail fast before paying for the cross contract call
+	can_exit(rules_str).unwrap_or_else(|| sys::panic());
+
+	let method_name = get_string(rules_str, "|kP|exit_check_method");
+	let args = substitute_placeholders(rules_str, get_string_opt(rules_str, "|kP|exit_check_args").unwrap_or("{}"));
+	let check_id = create_promise_batch(contract_id.to_string(), None);
+	promise_function_call(check_id, method_name, &args, 0, EXIT_CHECK_GAS);
+	let cb_id = create_promise_batch(sys_account_id(0), Some(check_id));
+	// the mode rides in front of the raw input so the callback exits the same way
+	// and unescapes the input again like any other entry point
+	let cb_args = format!("{}{}{}", mode, COMMA, get_input(false));
+	promise_function_call(cb_id, EXIT_CHECK_CALLBACK_METHOD_NAME, &cb_args, 0, CALLBACK_GAS);
+	true
+}
+
+/// # Safety
+/// only called by this contract after the exit check promise in `create_account_and_claim` or `exit_to_account`
+#[no_mangle]
+pub unsafe fn exit_check_callback() {
+	assert_self();
+
+	// the check passes only when the view succeeded and returned true
+	let status = near_sys::promise_result(0, REGISTER_0);
+	if status != 1 || register_read(REGISTER_0) != b"true" {
+		return log("exit check failed");
+	}
+
+	let rules_str = storage_read_str(RULES_KEY);
+	let funder = get_string(&rules_str, "|kP|funder").to_string();
+	assert_not_paused();
+	let cb_args = get_input(true);
+	let (mode, input_str) = split_once(&cb_args, COMMA);
+	user_exit(&rules_str, funder, mode, input_str);
+}
+
+/// non-funder e.g. user will have to pass exit conditions and repay before claiming
+fn user_exit(rules_str: &str, funder: String, mode: &str, input_str: &str) {
+	let exit_option = can_exit(rules_str);
+	let repay = exit_option.unwrap_or_else(|| sys::panic());
+
+	// non-funder will have to repay funder (and any split beneficiaries) from this account
+	let mut refund_id = repay_transfers(rules_str, funder, repay);
+	let mut paid_out = repay;
+
+	// the referrer is only rewarded when the user successfully exits
+	if let Some((referrer, reward)) = get_referral(rules_str) {
+		refund_id = create_promise_batch(referrer.to_string(), Some(refund_id));
 		unsafe {
 			near_sys::promise_batch_action_transfer(
 				refund_id,
-				repay.to_le_bytes().as_ptr() as u64,
+				reward.to_le_bytes().as_ptr() as u64,
 			)
 		}
-		Some(refund_id)
+		paid_out += reward;
+		emit_event("referral_payout", &format!("{{\"referrer\":\"{}\",\"amount\":\"{}\"}}", referrer, reward));
+	}
+
+	match mode {
+		CLAIM_MODE => claim(input_str, Some(refund_id)),
+		EXIT_TO_MODE => {
+			// validated by exit_to_account before any exit check
+			let account_id = get_json_string(input_str, "account_id").unwrap_or_else(|| sys::panic());
+			exit_to(account_id, get_json_bool(input_str, "delete_account"), paid_out, refund_id)
+		}
+		_ => sys::panic(),
+	}
+}
+
+/// move everything left after repay (and referral) to an existing account once the refund has finished
+/// without delete_account the trial state, code and key are removed and only its storage reserve is left
+/// repay and referral transfers were already deducted from the account balance when they were scheduled
+fn exit_to(account_id: &str, delete_account: bool, repay: u128, refund_id: u64) {
+	let amount = if delete_account {
+		let exit_id = create_promise_batch(sys_account_id(0), Some(refund_id));
+		unsafe {
+			near_sys::promise_batch_action_delete_account(
+				exit_id,
+				account_id.len() as u64,
+				account_id.as_ptr() as u64,
+			);
+		}
+		account_balance()
 	} else {
-		None
+		// nothing is left to drive the trial, so the rules and counters cannot be reused
+		clear_trial_storage();
+		let storage_reserve = storage_usage() as u128 * STORAGE_BYTE_COST;
+		let amount = account_balance().saturating_sub(storage_reserve);
+		let exit_id = create_promise_batch(account_id.to_string(), Some(refund_id));
+		unsafe {
+			near_sys::promise_batch_action_transfer(
+				exit_id,
+				amount.to_le_bytes().as_ptr() as u64,
+			)
+		}
+		// the trial key and code go too, exit_to_account is always signed with the trial key
+		let cleanup_id = create_promise_batch(sys_account_id(0), Some(exit_id));
+		let signer_pk_bytes = sys_signer_pk_bytes();
+		unsafe {
+			near_sys::promise_batch_action_deploy_contract(
+				cleanup_id,
+				0,
+				"".as_ptr() as u64,
+			);
+			near_sys::promise_batch_action_delete_key(
+				cleanup_id,
+				signer_pk_bytes.len() as u64,
+				signer_pk_bytes.as_ptr() as u64,
+			);
+		}
+		amount
 	};
-	
-	// parse the input and get the public key
-    let input_str = get_input(true);	
-	let (_, public_key_str) = split_once(&input_str, "\"new_public_key\":\"");
-	let (_, mut public_key_str) = split_once(public_key_str, "ed25519:");
-	public_key_str = &public_key_str[..public_key_str.len() - 2];
-	let public_key = string_to_base58(public_key_str);
-	// log(&format!("public_key_str: {}", public_key_str));
-	// log(&format!("public_key: {:?}", public_key));
-	// log(&format!("public_key_len: {}", public_key.len()));
-
-	// cleanup account storage, keys, deploy empty string for contract
-	storage_remove(RULES_KEY);
-	storage_remove(FLOOR_KEY);
+	emit_event("exit_to_account", &format!(
+		"{{\"account_id\":\"{}\",\"repay\":\"{}\",\"amount\":\"{}\",\"deleted\":{}}}",
+		account_id, repay, amount, delete_account,
+	));
+}
+
+/// swap the trial key for the new full access key(s) once any refund has finished
+fn claim(input_str: &str, refund_id: Option<u64>) {
+	// parse the input and get the public key(s) e.g. device and recovery keys
+	let public_keys = get_public_keys(input_str);
+
+	// optionally deploy a contract staged by the funder instead of the empty one
+	let staged = get_staged_contracts();
+	let code = match get_json_string(input_str, "contract_hash") {
+		Some(hash) => {
+			if !staged.iter().any(|h| h == hash) {
+				log("contract not staged");
+				sys::panic();
+			}
+			storage_read(&code_key(hash))
+		}
+		None => vec![],
+	};
+
+	// cleanup account storage, keys, deploy empty string (or the staged code) for contract
+	clear_trial_storage();
 	// promise for add key .then from refund make sure refund finishes first
 	let exit_id = create_promise_batch(sys_account_id(0), refund_id);
 	unsafe {
 		near_sys::promise_batch_action_deploy_contract(
 			exit_id,
-			0,
-			"".as_ptr() as u64,
+			code.len() as u64,
+			code.as_ptr() as u64,
 		);
-		let signer_pk_bytes = sys_signer_pk_bytes();
+		// user exits (always chained on the repay) are signed with the trial key
+		// a takeover is signed with the funder's own key, which is not a key of this account
+		if refund_id.is_some() {
+			let signer_pk_bytes = sys_signer_pk_bytes();
 
-		near_sys::promise_batch_action_delete_key(
-			exit_id,
-			signer_pk_bytes.len() as u64,
-			signer_pk_bytes.as_ptr() as u64,
-		);
-		near_sys::promise_batch_action_add_key_with_full_access(
-			exit_id,
-			public_key.len() as u64,
-			public_key.as_ptr() as u64,
-			0,
-		);
+			near_sys::promise_batch_action_delete_key(
+				exit_id,
+				signer_pk_bytes.len() as u64,
+				signer_pk_bytes.as_ptr() as u64,
+			);
+			// optionally re-add the trial key as a function call key so existing app sessions keep working
+			if let Some(receiver_id) = get_json_string(input_str, "trial_key_receiver_id") {
+				if !is_valid_account_id(receiver_id) {
+					log("invalid trial_key_receiver_id");
+					sys::panic();
+				}
+				// comma separated, empty allows any method
+				let method_names = get_json_string(input_str, "trial_key_method_names").unwrap_or("");
+				// 0 would be an unlimited allowance, so a bounded one must be given
+				let allowance: u128 = get_json_string(input_str, "trial_key_allowance")
+					.and_then(|a| a.parse().ok())
+					.unwrap_or(0);
+				if allowance == 0 {
+					log("trial_key_allowance required");
+					sys::panic();
+				}
+				near_sys::promise_batch_action_add_key_with_function_call(
+					exit_id,
+					signer_pk_bytes.len() as u64,
+					signer_pk_bytes.as_ptr() as u64,
+					0,
+					allowance.to_le_bytes().as_ptr() as u64,
+					receiver_id.len() as u64,
+					receiver_id.as_ptr() as u64,
+					method_names.len() as u64,
+					method_names.as_ptr() as u64,
+				);
+			}
+		}
+		for public_key in &public_keys {
+			near_sys::promise_batch_action_add_key_with_full_access(
+				exit_id,
+				public_key.len() as u64,
+				public_key.as_ptr() as u64,
+				0,
+			);
+		}
+	}
+}
+
+// helpers
+
+/// remove the rules, counters and staged contracts of the trial
+fn clear_trial_storage() {
+	for hash in &get_staged_contracts() {
+		storage_remove(&code_key(hash));
+	}
+	storage_remove(STAGED_KEY);
+	storage_remove(RULES_KEY);
+	storage_remove(FLOOR_KEY);
+	storage_remove(USAGE_KEY);
+	storage_remove(PAUSED_KEY);
+	storage_remove(SETUP_KEY);
+	storage_remove(FT_SPENT_KEY);
+}
+
+/// base58 hashes of contracts the funder staged for exit
+pub(crate) fn get_staged_contracts() -> Vec<String> {
+	if !storage_has_key(STAGED_KEY) {
+		return vec![];
+	}
+	storage_read_str(STAGED_KEY).split(",").map(|h| h.to_string()).collect()
+}
+
+pub(crate) fn code_key(hash: &str) -> Vec<u8> {
+	[CODE_KEY_PREFIX, hash.as_bytes()].concat()
+}
+
+/// panics unless the predecessor is the funder in the rules, returns the funder
+pub(crate) fn assert_funder(rules_str: &str) -> String {
+	let funder = get_string(rules_str, "|kP|funder").to_string();
+	if funder != sys_account_id(1) {
+		log("only funder");
+		sys::panic();
+	}
+	funder
+}
+
+/// set a single rule, recording the change if the value differs
+pub(crate) fn update_rule(rules_str: &str, rule: &str, value: &str, changes: &mut Vec<String>) -> String {
+	let key = format!("|kP|{}", rule);
+	let old = get_string_opt(rules_str, &key).unwrap_or("");
+	if old == value {
+		return rules_str.to_string();
+	}
+	changes.push(format!("{{\"rule\":\"{}\",\"old\":\"{}\",\"new\":\"{}\"}}", rule, old, value));
+	update_string(rules_str, &key, value)
+}
+
+/// panics unless called with the trial access key, which can only call this contract
+pub(crate) fn assert_trial_key() {
+	if sys_account_id(1) != sys_account_id(0) {
+		log("only trial key");
+		sys::panic();
+	}
+}
+
+/// panics unless this contract called itself, used to guard callbacks
+pub(crate) fn assert_self() {
+	if sys_account_id(1) != sys_account_id(0) {
+		log("only self");
+		sys::panic();
+	}
+}
+
+pub(crate) fn assert_not_paused() {
+	if storage_has_key(PAUSED_KEY) {
+		log("trial paused");
+		sys::panic();
+	}
+}
+
+/// rules with "locked" set are frozen against the user once the first execute happened
+pub(crate) fn is_rules_locked(rules_str: &str) -> bool {
+	get_string_opt(rules_str, "|kP|locked") == Some("true") && Usage::read().executes > 0
+}
+
+/// panics if locked rules are replaced by rules making exit harder for the user
+pub(crate) fn assert_no_rug(old_rules: &str, new_rules: &str) {
+	if !is_rules_locked(old_rules) {
+		return;
+	}
+	// lower repay and higher exit floor are in the user's favor, the funder receiving repay stays the same
+	let in_favor = get_string_opt(new_rules, "|kP|locked") == Some("true")
+		&& get_u128(new_rules, "|kP|repay") <= get_u128(old_rules, "|kP|repay")
+		&& get_u128(new_rules, "|kP|floor") >= get_u128(old_rules, "|kP|floor")
+		&& get_string(new_rules, "|kP|funder") == get_string(old_rules, "|kP|funder")
+		&& get_string_opt(new_rules, "|kP|referrer") == get_string_opt(old_rules, "|kP|referrer")
+		&& get_referral(new_rules).map(|(_, r)| r).unwrap_or(0) <= get_referral(old_rules).map(|(_, r)| r).unwrap_or(0)
+		&& match (get_expiry(old_rules), get_expiry(new_rules)) {
+			(_, None) => true,
+			(None, Some(_)) => false,
+			(Some(old), Some(new)) => new >= old,
+		}
+		&& REPAY_RULES.iter().all(|r| get_string_opt(old_rules, r) == get_string_opt(new_rules, r))
+		&& (get_string_opt(new_rules, "|kP|exit_check_contract").is_none()
+			|| EXIT_CHECK_RULES.iter().all(|r| get_string_opt(old_rules, r) == get_string_opt(new_rules, r)))
+		&& activity_in_favor(old_rules, new_rules)
+		&& grace_in_favor(old_rules, new_rules)
+		&& nft_locked_in_favor(old_rules, new_rules)
+		&& takeover_in_favor(old_rules, new_rules);
+	if !in_favor {
+		log("rules locked");
+		sys::panic();
+	}
+}
+
+/// locked nft contracts may only be unlocked, not added
+fn nft_locked_in_favor(old_rules: &str, new_rules: &str) -> bool {
+	let old: Vec<&str> = get_string_opt(old_rules, "|kP|nft_locked").unwrap_or("").split(",").collect();
+	get_string_opt(new_rules, "|kP|nft_locked").unwrap_or("").split(",").all(|c| c.is_empty() || old.contains(&c))
+}
+
+/// a locked grace period may only get longer and waive more conditions
+fn grace_in_favor(old_rules: &str, new_rules: &str) -> bool {
+	let grace_period = |rules: &str| get_string_opt(rules, "|kP|grace_period").map(|_| get_u128(rules, "|kP|grace_period")).unwrap_or(0);
+	let old_relax: Vec<&str> = get_string_opt(old_rules, "|kP|grace_relax").unwrap_or("").split(",").collect();
+	let new_relax: Vec<&str> = get_string_opt(new_rules, "|kP|grace_relax").unwrap_or("").split(",").collect();
+	grace_period(new_rules) >= grace_period(old_rules) && old_relax.iter().all(|r| new_relax.contains(r))
+}
+
+/// locked activity predicates may only be relaxed or removed
+fn activity_in_favor(old_rules: &str, new_rules: &str) -> bool {
+	let lower_or_unset = |rule: &str| match (get_string_opt(old_rules, rule), get_string_opt(new_rules, rule)) {
+		(_, None) => true,
+		(None, Some(_)) => false,
+		(Some(_), Some(_)) => get_u128(new_rules, rule) <= get_u128(old_rules, rule),
+	};
+	let methods_subset = match (get_string_opt(old_rules, "|kP|exit_methods"), get_string_opt(new_rules, "|kP|exit_methods")) {
+		(_, None) => true,
+		(None, Some(_)) => false,
+		(Some(old), Some(new)) => {
+			let old: Vec<&str> = old.split(",").collect();
+			new.split(",").all(|m| old.contains(&m))
+		}
+	};
+	lower_or_unset("|kP|exit_calls") && lower_or_unset("|kP|exit_days") && methods_subset
+}
+
+/// a locked takeover policy may only become "never" or keep its policy with a longer inactivity window
+fn takeover_in_favor(old_rules: &str, new_rules: &str) -> bool {
+	let (old, new) = (get_takeover_policy(old_rules), get_takeover_policy(new_rules));
+	if new == "never" {
+		return true;
+	}
+	if old != new {
+		return false;
+	}
+	new != "inactivity" || get_u128(new_rules, "|kP|takeover_inactivity") >= get_u128(old_rules, "|kP|takeover_inactivity")
+}
+
+/// repay owed right now, "repay" declines according to the optional "repay_schedule"
+/// linear: from repay to repay_end over repay_duration (ns) starting at the first execute
+/// steps: repay_steps "executes:amount,..." where the last step reached by the execute count applies
+pub(crate) fn get_repay(rules_str: &str) -> u128 {
+	let repay = get_u128(rules_str, "|kP|repay");
+	let schedule = match get_string_opt(rules_str, "|kP|repay_schedule") {
+		Some(schedule) => schedule,
+		None => return repay,
+	};
+	let usage = Usage::read();
+	match schedule {
+		"linear" => {
+			let repay_end = get_string_opt(rules_str, "|kP|repay_end")
+				.map(|r| r.parse().ok().unwrap_or_else(|| sys::panic()))
+				.unwrap_or(0u128)
+				.min(repay);
+			if usage.executes == 0 {
+				return repay;
+			}
+			// work in ms so the multiplication below cannot overflow
+			let duration = get_u128(rules_str, "|kP|repay_duration") / NS_PER_MS as u128;
+			let elapsed = ((block_timestamp() - usage.first_activity) / NS_PER_MS) as u128;
+			if elapsed >= duration {
+				return repay_end;
+			}
+			repay - (repay - repay_end) * elapsed / duration
+		}
+		"steps" => {
+			let mut current = repay;
+			for step in get_string(rules_str, "|kP|repay_steps").split(",") {
+				let (executes, amount) = split_once(step, ":");
+				let executes: u64 = executes.parse().ok().unwrap_or_else(|| sys::panic());
+				if usage.executes >= executes {
+					current = amount.parse().ok().unwrap_or_else(|| sys::panic());
+				}
+			}
+			current
+		}
+		_ => sys::panic(),
+	}
+}
+
+/// total repay at exit, combining the fixed (scheduled) repay with the optional
+/// "repay_percent" (basis points of the balance) using "repay_combine" "max" (default) or "min"
+pub(crate) fn get_exit_repay(rules_str: &str, balance: u128) -> u128 {
+	let repay = get_repay(rules_str);
+	let percent = match get_string_opt(rules_str, "|kP|repay_percent") {
+		Some(percent) => percent.parse::<u128>().ok().unwrap_or_else(|| sys::panic()).min(BASIS_POINTS),
+		None => return repay,
+	};
+	let repay_percent = balance / BASIS_POINTS * percent;
+	match get_string_opt(rules_str, "|kP|repay_combine").unwrap_or("max") {
+		"max" => repay.max(repay_percent),
+		"min" => repay.min(repay_percent),
+		_ => sys::panic(),
+	}
+}
+
+/// panics on rules that would only fail once the user tries to exit
+pub(crate) fn assert_valid_rules(rules_str: &str) {
+	if let Some(split) = get_string_opt(rules_str, "|kP|repay_split") {
+		let mut total: u128 = 0;
+		for share in split.split(",") {
+			let (account_id, basis_points) = share.split_once(":").unwrap_or(("", ""));
+			let basis_points: Option<u128> = basis_points.parse().ok();
+			total = total.saturating_add(basis_points.unwrap_or(u128::MAX));
+			if !is_valid_account_id(account_id) || total > BASIS_POINTS {
+				log("invalid repay_split");
+				sys::panic();
+			}
+		}
+	}
+	if let Some(referrer) = get_string_opt(rules_str, "|kP|referrer") {
+		let reward = get_string_opt(rules_str, "|kP|referral_reward").and_then(|r| r.parse::<u128>().ok());
+		if !is_valid_account_id(referrer) || reward.is_none() {
+			log("invalid referrer");
+			sys::panic();
+		}
+	}
+}
+
+/// chain one transfer per "repay_split" beneficiary ("account:basis_points,...") and the rest to the funder
+/// returns the last promise so the exit batch runs after every transfer
+pub(crate) fn repay_transfers(rules_str: &str, funder: String, repay: u128) -> u64 {
+	let mut transfers: Vec<(String, u128)> = vec![];
+	let mut remaining = repay;
+	if let Some(split) = get_string_opt(rules_str, "|kP|repay_split") {
+		for share in split.split(",") {
+			let (account_id, basis_points) = split_once(share, ":");
+			let basis_points: u128 = basis_points.parse().ok().unwrap_or_else(|| sys::panic());
+			let amount = repay / BASIS_POINTS * basis_points;
+			if amount > remaining {
+				log("repay split over 100%");
+				sys::panic();
+			}
+			remaining -= amount;
+			transfers.push((account_id.to_string(), amount));
+		}
 	}
+	transfers.push((funder, remaining));
+
+	let mut prev_id = None;
+	for (account_id, amount) in transfers {
+		let id = create_promise_batch(account_id, prev_id);
+		unsafe {
+			near_sys::promise_batch_action_transfer(
+				id,
+				amount.to_le_bytes().as_ptr() as u64,
+			)
+		}
+		prev_id = Some(id);
+	}
+	prev_id.unwrap_or_else(|| sys::panic())
 }
 
-/// helpers
+/// when the funder may take over the trial via create_account_and_claim, defaults to "always"
+pub(crate) fn get_takeover_policy(rules_str: &str) -> &str {
+	get_string_opt(rules_str, "|kP|takeover").unwrap_or("always")
+}
+
+pub(crate) fn takeover_allowed(rules_str: &str) -> bool {
+	let now = block_timestamp();
+	match get_takeover_policy(rules_str) {
+		"always" => true,
+		"never" => false,
+		"expiry" => is_expired(get_expiry(rules_str)),
+		"inactivity" => {
+			let window: u64 = get_string(rules_str, "|kP|takeover_inactivity").parse().ok().unwrap_or_else(|| sys::panic());
+			// a trial that was never used counts as active since setup
+			let setup_at = u64::from_le_bytes(storage_read(SETUP_KEY).try_into().ok().unwrap_or_else(|| sys::panic()));
+			let last_active = Usage::read().last_activity.max(setup_at);
+			now > last_active.saturating_add(window)
+		}
+		_ => sys::panic(),
+	}
+}
+
+/// optional "referrer" paid "referral_reward" from the trial balance on a successful user exit
+pub(crate) fn get_referral(rules_str: &str) -> Option<(&str, u128)> {
+	let referrer = get_string_opt(rules_str, "|kP|referrer")?;
+	Some((referrer, get_u128(rules_str, "|kP|referral_reward")))
+}
+
+/// optional block timestamp (ns) marking the end of the trial
+pub(crate) fn get_expiry(rules_str: &str) -> Option<u64> {
+	get_string_opt(rules_str, "|kP|expiry").map(|e| e.parse().ok().unwrap_or_else(|| sys::panic()))
+}
+
+pub(crate) fn is_expired(expiry: Option<u64>) -> bool {
+	expiry.map(|e| block_timestamp() > e).unwrap_or(false)
+}
+
+/// time (ns) until expiry, None when there is no expiry or it already passed
+pub(crate) fn time_remaining(rules_str: &str) -> Option<u64> {
+	get_expiry(rules_str).and_then(|e| e.checked_sub(block_timestamp()))
+}
+
+/// time remaining when inside the optional "grace_period" (ns) right before expiry
+pub(crate) fn grace_time_remaining(rules_str: &str) -> Option<u64> {
+	let grace_period: u64 = get_string_opt(rules_str, "|kP|grace_period")?.parse().ok().unwrap_or_else(|| sys::panic());
+	time_remaining(rules_str).filter(|remaining| *remaining <= grace_period)
+}
+
+/// optional numbers are returned as a json string or null
+pub(crate) fn json_opt_u64(value: Option<u64>) -> String {
+	value.map(|v| format!("\"{}\"", v)).unwrap_or_else(|| "null".to_string())
+}
+
+/// contracts, methods per contract and max attached deposit per contract from the rules
+pub(crate) fn get_allowances(rules_str: &str) -> (Vec<&str>, Vec<Vec<&str>>, Vec<u128>) {
+	let contracts: Vec<&str> = get_string(rules_str, "|kP|contracts").split(",").collect();
+	let methods: Vec<Vec<&str>> = get_string(rules_str, "|kP|methods").split(",").map(|s| s.split(":").collect()).collect();
+	let amounts: Vec<u128> = get_string(rules_str, "|kP|amounts")
+		.split(",")
+		.map(|a| {
+			let amount: u128 = a.parse().ok().unwrap_or_else(|| sys::panic());
+			amount
+		})
+		.collect();
+	(contracts, methods, amounts)
+}
 
 pub fn get_floor() -> u128 {
 	let floor_bytes = storage_read(FLOOR_KEY);
 	u128::from_le_bytes(floor_bytes.try_into().ok().unwrap_or_else(|| sys::panic()))
 }
 
-/// views
+// views
 
 #[no_mangle]
 pub(crate) unsafe fn get_rules() {
@@ -321,12 +1202,125 @@ pub(crate) unsafe fn get_rules() {
 	rules.pop();
 	// get current floor value as string
 	let floor_str = get_floor().to_string();
+	// whether locked rules are now enforced
+	let activated = Usage::read().executes > 0;
+	// active takeover policy, including the default when unset
+	let takeover_policy = get_takeover_policy(&rules).to_string();
 	// inject and return bytes
-    return_bytes(&format!("{},\"current_floor\":\"{}\"}}", rules, floor_str).as_bytes(), true);
+    return_bytes(format!("{},\"current_floor\":\"{}\",\"activated\":{},\"takeover_policy\":\"{}\"}}", rules, floor_str, activated, takeover_policy).as_bytes(), true);
 }
 
 #[no_mangle]
 pub(crate) unsafe fn get_key_information() {
-	let exit_option = can_exit(&storage_read_str(RULES_KEY));
-    return_value(format!("{{\"required_gas\":\"50000000000000\",\"yoctoNEAR\": \"0\",\"balance\":\"0\",\"trial_data\":{{\"exit\":{}}}}}", exit_option.is_some()).as_bytes());
+	let rules_str = storage_read_str(RULES_KEY);
+	let status = exit_status(&rules_str);
+	let (contracts, methods, amounts) = get_allowances(&rules_str);
+	// per contract allowances in the same order as the rules
+	let allowances: Vec<String> = contracts
+		.iter()
+		.enumerate()
+		.map(|(i, contract_id)| {
+			let methods: Vec<String> = methods[i].iter().map(|m| format!("\"{}\"", m)).collect();
+			format!(
+				"{{\"contract_id\":\"{}\",\"methods\":[{}],\"amount\":\"{}\"}}",
+				contract_id,
+				methods.join(","),
+				amounts[i],
+			)
+		})
+		.collect();
+	// the prepended registration needs gas on top of every transaction
+	let mut required_gas = REQUIRED_GAS;
+	if get_string_opt(&rules_str, "|kP|storage_deposit").is_some() {
+		required_gas += STORAGE_DEPOSIT_GAS;
+	}
+	// yoctoNEAR is the per use deposit in the keypom shape, spendable is whatever the account holds above the exit floor
+	return_value(format!(
+		"{{\"required_gas\":\"{}\",\"yoctoNEAR\":\"0\",\"balance\":\"{}\",\"trial_data\":{{\"exit\":{},\"spendable\":\"{}\",\"current_floor\":\"{}\",\"floor_exit\":\"{}\",\"repay\":\"{}\",\"callback_gas\":\"{}\",\"allowances\":[{}]}}}}",
+		required_gas,
+		status.balance,
+		status.failing.is_empty(),
+		status.balance.saturating_sub(status.floor_exit),
+		status.floor,
+		status.floor_exit,
+		status.repay,
+		CALLBACK_GAS,
+		allowances.join(","),
+	).as_bytes());
 }
+
+#[no_mangle]
+pub(crate) unsafe fn get_exit_status() {
+	let rules_str = storage_read_str(RULES_KEY);
+	let status = exit_status(&rules_str);
+	let failing: Vec<String> = status.failing.iter().map(|f| format!("\"{}\"", f)).collect();
+	return_value(format!(
+		"{{\"exit\":{},\"balance\":\"{}\",\"repay\":\"{}\",\"repay_schedule\":\"{}\",\"referral_reward\":\"{}\",\"current_floor\":\"{}\",\"floor_exit\":\"{}\",\"remaining_spend\":\"{}\",\"expiry\":{},\"time_remaining\":{},\"in_grace_period\":{},\"exit_check\":{},\"successful_calls\":{},\"active_days\":{},\"failing\":[{}]}}",
+		status.failing.is_empty(),
+		status.balance,
+		status.repay,
+		get_string_opt(&rules_str, "|kP|repay_schedule").unwrap_or("fixed"),
+		status.referral_reward,
+		status.floor,
+		status.floor_exit,
+		status.floor.saturating_sub(status.floor_exit),
+		json_opt_u64(status.expiry),
+		json_opt_u64(time_remaining(&rules_str)),
+		grace_time_remaining(&rules_str).is_some(),
+		// views cannot make cross contract calls, so only the configured check is reported
+		get_string_opt(&rules_str, "|kP|exit_check_contract")
+			.map(|c| format!("{{\"contract_id\":\"{}\",\"method_name\":\"{}\"}}", c, get_string(&rules_str, "|kP|exit_check_method")))
+			.unwrap_or_else(|| "null".to_string()),
+		status.usage.successful_calls,
+		status.usage.active_days,
+		failing.join(","),
+	).as_bytes());
+}
+
+#[no_mangle]
+pub(crate) unsafe fn get_usage() {
+	return_value(Usage::read().to_json().as_bytes());
+}
+
+#[no_mangle]
+pub(crate) unsafe fn get_pause_status() {
+	let paused = storage_has_key(PAUSED_KEY);
+	let reason = if paused { storage_read_str(PAUSED_KEY) } else { String::new() };
+	return_value(format!("{{\"paused\":{},\"reason\":\"{}\"}}", paused, reason).as_bytes());
+}
+
+#[no_mangle]
+pub(crate) unsafe fn get_staged_contract_hashes() {
+	let staged: Vec<String> = get_staged_contracts().iter().map(|h| format!("\"{}\"", h)).collect();
+	return_value(format!("[{}]", staged.join(",")).as_bytes());
+}
+
+#[no_mangle]
+pub(crate) unsafe fn get_time_remaining() {
+	let rules_str = storage_read_str(RULES_KEY);
+	let expiry = get_expiry(&rules_str);
+	return_value(format!(
+		"{{\"expiry\":{},\"time_remaining\":{},\"expired\":{},\"in_grace_period\":{}}}",
+		json_opt_u64(expiry),
+		json_opt_u64(time_remaining(&rules_str)),
+		is_expired(expiry),
+		grace_time_remaining(&rules_str).is_some(),
+	).as_bytes());
+}
+
+#[no_mangle]
+pub(crate) unsafe fn get_ft_allowances() {
+	let rules_str = storage_read_str(RULES_KEY);
+	let spent = get_ft_spent();
+	let allowances: Vec<String> = get_ft_allowances_rule(&rules_str)
+		.iter()
+		.map(|(token_id, per_call, total)| format!(
+			"{{\"token_id\":\"{}\",\"per_call\":\"{}\",\"total\":\"{}\",\"spent\":\"{}\"}}",
+			token_id,
+			per_call,
+			total,
+			ft_spent_for(&spent, token_id),
+		))
+		.collect();
+	return_value(format!("[{}]", allowances.join(",")).as_bytes());
+}
\ No newline at end of file
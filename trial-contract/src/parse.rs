@@ -2,9 +2,15 @@ use crate::*;
 
 /// helper to get next value from string key in stringified json
 pub(crate) fn get_string<'a>(string: &'a str, key: &str) -> &'a str {
-    let (_, value) = split_once(string, key);
-    let (value, _) = split_once(value, PARAM_STOP);
-    &value[3..]
+    get_string_opt(string, key).unwrap_or_else(|| sys::panic())
+}
+
+/// helper to get the value of an optional key in stringified json
+/// matches the closing quote of the key so "|kP|floor" never matches "|kP|floor_..."
+pub(crate) fn get_string_opt<'a>(string: &'a str, key: &str) -> Option<&'a str> {
+    let (_, value) = string.split_once(&format!("{}\":\"", key))?;
+    let (value, _) = value.split_once(PARAM_STOP)?;
+    Some(value)
 }
 
 /// helper to get and parse the next u128 value from a string key in stringified json
@@ -14,9 +20,156 @@ pub(crate) fn get_u128(str: &str, key: &str) -> u128 {
     amount.parse().ok().unwrap_or_else(|| sys::panic())
 }
 
-pub(crate) fn get_input(strip_slashes: bool) -> String {
+/// helper to get an optional string value from plain (unwrapped) json input
+/// the value is returned still json escaped
+pub(crate) fn get_json_string<'a>(string: &'a str, key: &str) -> Option<&'a str> {
+    let (_, value) = string.split_once(&format!("\"{}\":\"", key))?;
+    let mut escaped = false;
+    for (i, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => return Some(&value[..i]),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// NEAR account id rules: 2-64 chars of lowercase alphanumerics separated by single '.', '-' or '_'
+pub(crate) fn is_valid_account_id(account_id: &str) -> bool {
+    if account_id.len() < 2 || account_id.len() > 64 {
+        return false;
+    }
+    let mut last_separator = true;
+    for c in account_id.bytes() {
+        let separator = matches!(c, b'.' | b'-' | b'_');
+        if !separator && !c.is_ascii_lowercase() && !c.is_ascii_digit() {
+            return false;
+        }
+        if separator && last_separator {
+            return false;
+        }
+        last_separator = separator;
+    }
+    !last_separator
+}
+
+/// helper to check an optional boolean flag in plain (unwrapped) json input, defaults to false
+pub(crate) fn get_json_bool(string: &str, key: &str) -> bool {
+    string.contains(&format!("\"{}\":true", key))
+}
+
+/// raw value of a top level field in a json object (strings keep their quotes)
+/// walks the whole object so nested or quoted look-alikes are never matched and
+/// panics on malformed json, escaped keys or duplicate fields so checks cannot be bypassed
+pub(crate) fn get_json_field<'a>(json: &'a str, field: &str) -> Option<&'a str> {
+    let bytes = json.as_bytes();
+    let mut i = skip_whitespace(bytes, 0);
+    if bytes.get(i) != Some(&b'{') {
+        sys::panic()
+    }
+    i = skip_whitespace(bytes, i + 1);
+    let mut found = None;
+    if bytes.get(i) == Some(&b'}') {
+        return found;
+    }
+    loop {
+        if bytes.get(i) != Some(&DOUBLE_QUOTE_BYTE) {
+            sys::panic()
+        }
+        let key_end = skip_json_string(bytes, i);
+        let key = &json[i + 1..key_end - 1];
+        if key.contains('\\') {
+            sys::panic()
+        }
+        i = skip_whitespace(bytes, key_end);
+        if bytes.get(i) != Some(&b':') {
+            sys::panic()
+        }
+        i = skip_whitespace(bytes, i + 1);
+        let value_end = skip_json_value(bytes, i);
+        if key == field {
+            if found.is_some() {
+                sys::panic()
+            }
+            found = Some(&json[i..value_end]);
+        }
+        i = skip_whitespace(bytes, value_end);
+        match bytes.get(i) {
+            Some(b',') => i = skip_whitespace(bytes, i + 1),
+            Some(b'}') => return found,
+            _ => sys::panic(),
+        }
+    }
+}
+
+fn skip_whitespace(bytes: &[u8], mut i: usize) -> usize {
+    while i < bytes.len() && matches!(bytes[i], b' ' | b'\t' | b'\n' | b'\r') {
+        i += 1;
+    }
+    i
+}
+
+/// index after the closing quote of the string starting at i
+fn skip_json_string(bytes: &[u8], mut i: usize) -> usize {
+    i += 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            DOUBLE_QUOTE_BYTE => return i + 1,
+            _ => i += 1,
+        }
+    }
+    sys::panic()
+}
+
+/// index after the value starting at i (string, object, array or literal)
+fn skip_json_value(bytes: &[u8], mut i: usize) -> usize {
+    match bytes.get(i) {
+        Some(&DOUBLE_QUOTE_BYTE) => skip_json_string(bytes, i),
+        Some(b'{') | Some(b'[') => {
+            let mut depth = 0;
+            while i < bytes.len() {
+                match bytes[i] {
+                    DOUBLE_QUOTE_BYTE => {
+                        i = skip_json_string(bytes, i);
+                        continue;
+                    }
+                    b'{' | b'[' => depth += 1,
+                    b'}' | b']' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return i + 1;
+                        }
+                    }
+                    _ => {}
+                }
+                i += 1;
+            }
+            sys::panic()
+        }
+        Some(_) => {
+            let start = i;
+            while i < bytes.len() && !matches!(bytes[i], b',' | b'}' | b']' | b' ' | b'\t' | b'\n' | b'\r') {
+                i += 1;
+            }
+            if i == start {
+                sys::panic()
+            }
+            i
+        }
+        None => sys::panic(),
+    }
+}
+
+pub(crate) fn get_input_bytes() -> Vec<u8> {
     unsafe { near_sys::input(REGISTER_0) };
-    let input = register_read(REGISTER_0);
+    register_read(REGISTER_0)
+}
+
+pub(crate) fn get_input(strip_slashes: bool) -> String {
+    let input = get_input_bytes();
     // if from_borsh {
     //     input = input[1..input.len()-1].to_vec();
     // }
@@ -33,7 +186,7 @@ pub(crate) fn split_once<'a>(string: &'a str, del: &str) -> (&'a str, &'a str) {
 
 // decode base58 public keys
 
-const B58_DIGITS_MAP: &'static [i8] = &[
+const B58_DIGITS_MAP: &[i8] = &[
 	-1,-1,-1,-1,-1,-1,-1,-1,-1,-1,-1,-1,-1,-1,-1,-1,
 	-1,-1,-1,-1,-1,-1,-1,-1,-1,-1,-1,-1,-1,-1,-1,-1,
 	-1,-1,-1,-1,-1,-1,-1,-1,-1,-1,-1,-1,-1,-1,-1,-1,
@@ -46,7 +199,7 @@ const B58_DIGITS_MAP: &'static [i8] = &[
 
 pub(crate) fn string_to_base58(string: &str) -> Vec<u8> {
     let mut bin = [0u8; 132];
-    let mut out = [0u32; (132 + 3) / 4];
+    let mut out = [0u32; 132_usize.div_ceil(4)];
     let bytesleft = (bin.len() % 4) as u8;
     let zeromask = match bytesleft {
         0 => 0u32,
@@ -111,7 +264,7 @@ pub(crate) fn string_to_base58(string: &str) -> Vec<u8> {
         bin[i] = ((out[j] >> 0x18) & 0xff) as u8;
         bin[i + 1] = ((out[j] >> 0x10) & 0xff) as u8;
         bin[i + 2] = ((out[j] >> 8) & 0xff) as u8;
-        bin[i + 3] = ((out[j] >> 0) & 0xff) as u8;
+        bin[i + 3] = (out[j] & 0xff) as u8;
         i += 4;
         j += 1;
     }
@@ -120,21 +273,87 @@ pub(crate) fn string_to_base58(string: &str) -> Vec<u8> {
     bin[leading_zeros - zcount - 1..].to_vec()
 }
 
-// pub(crate) fn update_string(string: &str, key: &str, val: &str) -> String {
-//     let mut ret: String = String::new();
-//     let (left, right) = string.split_once(key).unwrap_or_else(|| sys::panic());
-//     let (_, right) = right.split_once(PARAM_STOP).unwrap_or_else(|| sys::panic());
-    
-//     ret.push_str(left);
-//     ret.push_str(key);
-//     ret.push_str("\",\"");
-//     ret.push_str(val);
-//     ret.push_str(PARAM_STOP);
-//     ret.push_str(right);
+/// parse a NEAR public key string ("ed25519:..." or "secp256k1:...") into the curve byte followed by the key data
+pub(crate) fn parse_public_key(public_key: &str) -> Vec<u8> {
+    let (curve, data, len) = match public_key.split_once(':') {
+        Some(("ed25519", data)) => (0u8, data, 32),
+        Some(("secp256k1", data)) => (1u8, data, 64),
+        _ => {
+            log("invalid public key");
+            sys::panic()
+        }
+    };
+    // decoded keys come back with one leading zero byte, which becomes the curve byte
+    let mut key = string_to_base58(data);
+    if key.len() != len + 1 {
+        log("invalid public key length");
+        sys::panic()
+    }
+    key[0] = curve;
+    key
+}
 
-//     ret
-// }
+/// public keys from either "new_public_key" or a "new_public_keys" array in plain json input
+pub(crate) fn get_public_keys(input_str: &str) -> Vec<Vec<u8>> {
+    match get_json_field(input_str, "new_public_keys") {
+        Some(keys) => {
+            let keys = keys.strip_prefix('[').and_then(|k| k.strip_suffix(']')).unwrap_or_else(|| sys::panic());
+            // an empty array leaves one empty key, which fails to parse
+            keys.split(',')
+                .map(|k| parse_public_key(k.trim().trim_matches(DOUBLE_QUOTE_BYTE as char)))
+                .collect()
+        }
+        None => vec![parse_public_key(get_json_string(input_str, "new_public_key").unwrap_or_else(|| sys::panic()))],
+    }
+}
+
+// encode hashes as base58 (e.g. contract code hashes)
 
+const B58_ALPHABET: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+pub(crate) fn base58_encode(bytes: &[u8]) -> String {
+    // base58 digits, least significant first
+    let mut digits: Vec<u8> = vec![];
+    for byte in bytes {
+        let mut carry = *byte as u32;
+        for digit in digits.iter_mut() {
+            carry += (*digit as u32) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+    let mut ret = String::new();
+    for _ in bytes.iter().take_while(|b| **b == 0) {
+        ret.push('1');
+    }
+    for digit in digits.iter().rev() {
+        ret.push(B58_ALPHABET[*digit as usize] as char);
+    }
+    ret
+}
+
+/// helper to set the value of a key in stringified json, appending the key if missing
+pub(crate) fn update_string(string: &str, key: &str, val: &str) -> String {
+    let mut ret: String = String::new();
+    match string.split_once(&format!("{}\":\"", key)) {
+        Some((left, right)) => {
+            let (_, right) = split_once(right, PARAM_STOP);
+            ret.push_str(left);
+            ret.push_str(&format!("{}\":\"{}{}", key, val, PARAM_STOP));
+            ret.push_str(right);
+        }
+        None => {
+            let (left, _) = string.rsplit_once('}').unwrap_or_else(|| sys::panic());
+            ret.push_str(left);
+            ret.push_str(&format!(",\"{}\":\"{}{}}}", key, val, PARAM_STOP));
+        }
+    }
+    ret
+}
 
 // const HEX_CHARS: &[u8; 16] = b"0123456789abcdef";
 // const fn val(c: u8) -> u8 {
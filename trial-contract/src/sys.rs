@@ -8,6 +8,14 @@ pub fn log(message: &str) {
     }
 }
 
+/// log a NEP-297 event so indexers can follow the trial lifecycle
+pub(crate) fn emit_event(event: &str, data: &str) {
+    log(&format!(
+        "EVENT_JSON:{{\"standard\":\"{}\",\"version\":\"{}\",\"event\":\"{}\",\"data\":{}}}",
+        EVENT_STANDARD, EVENT_VERSION, event, data
+    ));
+}
+
 pub(crate) fn create_promise_batch(account_id: String, prev_id: Option<u64>) -> u64 {
     // then make another promise back receiver == current_account_id
     if let Some(prev_id) = prev_id {
@@ -28,10 +36,24 @@ pub(crate) fn create_promise_batch(account_id: String, prev_id: Option<u64>) ->
     }
 }
 
+pub(crate) fn promise_function_call(id: u64, method_name: &str, args: &str, deposit: u128, gas: u64) {
+    unsafe {
+        near_sys::promise_batch_action_function_call(
+            id,
+            method_name.len() as u64,
+            method_name.as_ptr() as u64,
+            args.len() as u64,
+            args.as_ptr() as u64,
+            deposit.to_le_bytes().as_ptr() as u64,
+            gas,
+        );
+    }
+}
+
 pub(crate) fn return_bytes_format(bytes: &[u8], json: bool) -> Vec<u8> {
     let mut ret_data = vec![];
-    if json == true {
-        let bytes_str = alloc::str::from_utf8(&bytes).ok().unwrap_or_else(|| sys::panic());
+    if json {
+        let bytes_str = alloc::str::from_utf8(bytes).ok().unwrap_or_else(|| sys::panic());
         ret_data.extend_from_slice(bytes_str
             // .replace("\"", "\\\"")
             .replace("|kP|", "")
@@ -85,6 +107,10 @@ pub(crate) fn storage_remove(key: &[u8]) {
     }
 }
 
+pub(crate) fn storage_has_key(key: &[u8]) -> bool {
+    unsafe { near_sys::storage_has_key(key.len() as u64, key.as_ptr() as u64) == 1 }
+}
+
 pub(crate) fn storage_read(key: &[u8]) -> Vec<u8> {
     let key_exists =
         unsafe { near_sys::storage_read(key.len() as u64, key.as_ptr() as u64, REGISTER_0) };
@@ -101,6 +127,25 @@ pub(crate) fn account_balance() -> u128 {
     u128::from_le_bytes(buffer)
 }
 
+pub(crate) fn attached_deposit() -> u128 {
+    let buffer = [0u8; 16];
+    unsafe { near_sys::attached_deposit(buffer.as_ptr() as u64) };
+    u128::from_le_bytes(buffer)
+}
+
+pub(crate) fn block_timestamp() -> u64 {
+    unsafe { near_sys::block_timestamp() }
+}
+
+pub(crate) fn storage_usage() -> u64 {
+    unsafe { near_sys::storage_usage() }
+}
+
+pub(crate) fn sha256(value: &[u8]) -> Vec<u8> {
+    unsafe { near_sys::sha256(value.len() as u64, value.as_ptr() as u64, REGISTER_0) };
+    register_read(REGISTER_0)
+}
+
 pub(crate) fn sys_signer_pk_bytes() -> Vec<u8> {
     log("sys_signer_pk called!");
 
@@ -0,0 +1,137 @@
+use crate::*;
+
+/// counters of what the trial user actually did, persisted under USAGE_KEY
+/// stored as "executes,deposit,gas,first_activity,last_activity,successful_calls,active_days;contract:method:calls:successes;..."
+pub(crate) struct Usage {
+    pub executes: u64,
+    pub deposit: u128,
+    pub gas: u128,
+    pub first_activity: u64,
+    pub last_activity: u64,
+    pub successful_calls: u64,
+    pub active_days: u64,
+    /// (contract, method, calls issued, calls succeeded)
+    pub calls: Vec<(String, String, u64, u64)>,
+}
+
+impl Usage {
+    pub(crate) fn read() -> Self {
+        let mut usage = Usage {
+            executes: 0,
+            deposit: 0,
+            gas: 0,
+            first_activity: 0,
+            last_activity: 0,
+            successful_calls: 0,
+            active_days: 0,
+            calls: vec![],
+        };
+        if !storage_has_key(USAGE_KEY) {
+            return usage;
+        }
+
+        let usage_str = storage_read_str(USAGE_KEY);
+        let mut sections = usage_str.split(';');
+        let totals: Vec<&str> = sections.next().unwrap_or_else(|| sys::panic()).split(',').collect();
+        if totals.len() != 7 {
+            sys::panic()
+        }
+        usage.executes = parse_num(totals[0]);
+        usage.deposit = parse_num(totals[1]);
+        usage.gas = parse_num(totals[2]);
+        usage.first_activity = parse_num(totals[3]);
+        usage.last_activity = parse_num(totals[4]);
+        usage.successful_calls = parse_num(totals[5]);
+        usage.active_days = parse_num(totals[6]);
+
+        for call in sections {
+            let (contract_id, rest) = split_once(call, ":");
+            let (method_name, rest) = split_once(rest, ":");
+            let (calls, successes) = split_once(rest, ":");
+            usage.calls.push((contract_id.to_string(), method_name.to_string(), parse_num(calls), parse_num(successes)));
+        }
+        usage
+    }
+
+    pub(crate) fn write(&self) {
+        let mut usage_str = format!(
+            "{},{},{},{},{},{},{}",
+            self.executes,
+            self.deposit,
+            self.gas,
+            self.first_activity,
+            self.last_activity,
+            self.successful_calls,
+            self.active_days
+        );
+        for (contract_id, method_name, calls, successes) in &self.calls {
+            usage_str.push_str(&format!(";{}:{}:{}:{}", contract_id, method_name, calls, successes));
+        }
+        swrite(USAGE_KEY, usage_str.as_bytes());
+    }
+
+    /// bump the execute counter, activity timestamps and distinct active days
+    pub(crate) fn record_execute(&mut self) {
+        let now = block_timestamp();
+        if self.executes == 0 {
+            self.first_activity = now;
+        }
+        if self.executes == 0 || now / NS_PER_DAY != self.last_activity / NS_PER_DAY {
+            self.active_days += 1;
+        }
+        self.executes += 1;
+        self.last_activity = now;
+    }
+
+    /// count a call per contract and method, new pairs beyond MAX_USAGE_CALLS are not tracked
+    pub(crate) fn record_call(&mut self, contract_id: &str, method_name: &str) {
+        let tracked = self.calls.len();
+        match self.calls.iter_mut().find(|(c, m, _, _)| c == contract_id && m == method_name) {
+            Some((_, _, calls, _)) => *calls += 1,
+            None if tracked < MAX_USAGE_CALLS => {
+                self.calls.push((contract_id.to_string(), method_name.to_string(), 1, 0))
+            }
+            None => {}
+        }
+    }
+
+    /// called from the callback once the calls of a transaction succeeded,
+    /// successes never exceed the calls actually issued for the method
+    pub(crate) fn record_success(&mut self, contract_id: &str, method_name: &str) {
+        if let Some((_, _, calls, successes)) = self.calls.iter_mut().find(|(c, m, _, _)| c == contract_id && m == method_name) {
+            if *successes < *calls {
+                *successes += 1;
+                self.successful_calls += 1;
+            }
+        }
+    }
+
+    pub(crate) fn method_succeeded(&self, contract_id: &str, method_name: &str) -> bool {
+        self.calls.iter().any(|(c, m, _, successes)| c == contract_id && m == method_name && *successes > 0)
+    }
+
+    pub(crate) fn to_json(&self) -> String {
+        let calls: Vec<String> = self.calls
+            .iter()
+            .map(|(contract_id, method_name, calls, successes)| format!(
+                "{{\"contract_id\":\"{}\",\"method_name\":\"{}\",\"calls\":{},\"successes\":{}}}",
+                contract_id, method_name, calls, successes
+            ))
+            .collect();
+        format!(
+            "{{\"executes\":{},\"deposit\":\"{}\",\"gas\":\"{}\",\"first_activity\":\"{}\",\"last_activity\":\"{}\",\"successful_calls\":{},\"active_days\":{},\"calls\":[{}]}}",
+            self.executes,
+            self.deposit,
+            self.gas,
+            self.first_activity,
+            self.last_activity,
+            self.successful_calls,
+            self.active_days,
+            calls.join(","),
+        )
+    }
+}
+
+fn parse_num<T: core::str::FromStr>(num: &str) -> T {
+    num.parse().ok().unwrap_or_else(|| sys::panic())
+}